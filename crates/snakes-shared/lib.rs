@@ -57,8 +57,48 @@ impl Add<TurnDirection> for Direction {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum ClientMessage {
     SetName(String),
+    /// Unlocks a registered name, sent before `SetName`.
+    Authenticate(String),
+    /// How the snake looks to watchers, sent before `SetName`.
+    Appearance(Appearance),
     Turn(u64, TurnDirection),
     NoTurn(u64),
+    /// Shows watchers what the bot is up to on tick `tick_id`, at most once per tick.
+    /// Doesn't count as an answer to the tick.
+    Debug {
+        tick_id: u64,
+        annotations: Vec<Annotation>,
+    },
+}
+
+/// Picked by a player when joining, see [`ClientMessage::Appearance`].
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Appearance {
+    /// A `#rrggbb` color, without one the snake gets a color from the watchers' palette.
+    pub color: Option<String>,
+    pub skin: Skin,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Skin {
+    #[default]
+    Plain,
+    Striped,
+    Dotted,
+}
+
+/// Drawn over the board for watchers, see [`ClientMessage::Debug`].
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum Annotation {
+    /// Cells the bot plans to move through, in order.
+    Path(Vec<(usize, usize)>),
+    /// A cell the bot is going for.
+    Target((usize, usize)),
+    Label {
+        at: (usize, usize),
+        text: String,
+    },
 }
 
 #[derive(JsonSchema, Debug, Serialize, Deserialize, Clone)]
@@ -70,6 +110,30 @@ pub enum ServerMessage {
         your_position: (usize, usize),
         your_direction: Direction,
     },
+    /// Sent right before the server closes the connection.
+    Kicked { reason: String },
+    /// The server is going down, the connection will be closed in about `seconds_left` seconds.
+    ServerShuttingDown { seconds_left: u64 },
+    /// The lobby already has `max_players` players. Unless the client asked to watch
+    /// instead, the connection is closed.
+    LobbyFull { max_players: usize },
+    /// A message from the server admins.
+    Announcement { text: String },
+    /// The ranked queue found a match, the connection carries on in lobby `lobby`.
+    MatchFound { lobby: usize },
+    /// The match is over, everyone is put back at the start for the next one.
+    MatchEnded { standings: Vec<Standing> },
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Standing {
+    /// Players with the same length and deaths share a place.
+    pub place: usize,
+    pub name: String,
+    pub tail_len: usize,
+    pub death: usize,
+    /// The rating after this match, registered players only.
+    pub rating: Option<f64>,
 }
 
 pub type Map = Vec<MapPiece>;
@@ -93,17 +157,231 @@ impl std::fmt::Display for MapPiece {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+/// Everything sent to watchers.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub enum WatchMessage {
+    Update(WatchUpdate),
+    Announcement {
+        text: String,
+    },
+    /// Only sent to watchers inspecting a snake, see [`WatcherMessage::Inspect`].
+    Inspect(InspectEvent),
+    /// What the bot playing `snake` published with [`ClientMessage::Debug`].
+    Debug {
+        snake: usize,
+        tick_id: u64,
+        annotations: Vec<Annotation>,
+    },
+    Chat {
+        name: String,
+        text: String,
+    },
+    /// Only sent to the watcher whose chat message was not passed on.
+    ChatRefused {
+        reason: String,
+    },
+}
+
+/// Everything watchers may send.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub enum WatcherMessage {
+    /// Follow what the server and the snake with this id tell each other, `None` to stop.
+    Inspect(Option<usize>),
+    /// The name shown next to chat messages, watchers are `spectator` until they pick one.
+    SetName(String),
+    Chat(String),
+}
+
+/// What passed between the server and an inspected snake.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub enum InspectEvent {
+    /// The snake was sent a map update.
+    Sent {
+        snake: usize,
+        tick_id: u64,
+        your_position: (usize, usize),
+        your_direction: Direction,
+    },
+    /// The snake is behind and got no map update this tick.
+    Skipped {
+        snake: usize,
+        tick_id: u64,
+    },
+    /// The snake answered `tick_id`, `after_ms` after it was sent.
+    Answered {
+        snake: usize,
+        tick_id: u64,
+        turn: Option<TurnDirection>,
+        after_ms: Option<u64>,
+    },
+    /// The tick is over: the last answer, to `answered`, was applied and the
+    /// `dropped` ones before it were thrown away.
+    Applied {
+        snake: usize,
+        answered: Option<u64>,
+        turn: Option<TurnDirection>,
+        dropped: usize,
+    },
+    Kicked {
+        snake: usize,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct WatchUpdate {
     pub map: Map,
     pub map_size: (usize, usize),
     pub clients: Vec<PlayerData>,
 }
-#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct PlayerData {
     pub name: String,
     pub position: (usize, usize),
+    /// The cells behind the head, from the neck to the tip of the tail.
+    pub tail: Vec<(usize, usize)>,
     pub tail_len: usize,
     pub death: usize,
     pub id: usize,
+    /// How long the client took to answer its last answered tick, in milliseconds.
+    pub latency_ms: Option<u64>,
+    /// Only registered players have a rating.
+    pub rating: Option<f64>,
+    pub appearance: Appearance,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct LeaderboardEntry {
+    pub name: String,
+    /// Times the player joined a lobby and left it again.
+    pub sessions: u64,
+    /// Timed matches played to the end.
+    pub matches: u64,
+    pub max_length: usize,
+    pub apples: u64,
+    pub kills: u64,
+    pub deaths: u64,
+    pub avg_survival_ticks: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct RatingEntry {
+    pub name: String,
+    pub rating: f64,
+    pub matches: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct TournamentInfo {
+    pub id: usize,
+    pub status: TournamentStatus,
+    /// The matches of every round so far, a round starts once the previous one is done.
+    pub rounds: Vec<Vec<TournamentMatch>>,
+    pub standings: Vec<TournamentStanding>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum TournamentStatus {
+    Running { round: usize },
+    Finished,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct TournamentMatch {
+    pub lobby: usize,
+    pub players: Vec<String>,
+    pub finished: bool,
+    /// Missing when the lobby closed before the match was over.
+    pub standings: Option<Vec<Standing>>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct TournamentStanding {
+    pub name: String,
+    /// A point for every opponent finishing below, half a point for every tie.
+    pub points: f64,
+    pub matches: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct LobbySummary {
+    pub id: usize,
+    pub players: usize,
+    pub watchers: usize,
+    pub tick: usize,
+    /// Time between two ticks right now.
+    pub tick_ms: u64,
+    pub paused: bool,
+    pub map_size: (usize, usize),
+    pub thumbnail: Thumbnail,
+}
+
+/// A map scaled down so every cell covers a block of the real one.
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct Thumbnail {
+    pub size: (usize, usize),
+    pub map: Map,
+}
+
+/// A finished match, every update watchers got from its first tick to its last.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Replay {
+    pub id: usize,
+    pub lobby: usize,
+    pub standings: Vec<Standing>,
+    pub frames: Vec<WatchUpdate>,
+}
+
+/// A replay on `GET /replays`, without its frames.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ReplaySummary {
+    pub id: usize,
+    pub lobby: usize,
+    pub ticks: usize,
+    pub standings: Vec<Standing>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct CreatedLobby {
+    pub lobby: usize,
+    /// Needed to join a private lobby, pass it as `code` when connecting.
+    pub join_code: Option<String>,
+    /// Lets the creator pause, step and slow down the lobby through `/admin/lobbies/{id}`.
+    pub host_token: String,
+}
+
+/// Body of `POST /admin/lobbies/{id}`.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub enum AdminCommand {
+    /// Stop ticking until resumed, players and watchers stay connected.
+    Pause,
+    Resume,
+    /// Play a single tick, only while paused.
+    Step,
+    Kick {
+        /// The snake id, as seen in the map and by watchers.
+        id: usize,
+        reason: Option<String>,
+    },
+    /// Puts every snake back at the start, like at the end of a match.
+    ResetScores,
+    /// Ticks this often from now on, the game no longer speeds up by itself.
+    /// Hosts can't go below 200ms.
+    SetTickRate {
+        ms: u64,
+    },
+    SpawnApple {
+        x: usize,
+        y: usize,
+    },
+    /// Shown to players and watchers.
+    Announce(String),
+    /// Drop chat messages from watchers with this name, even once they pick another one,
+    /// and keep others from taking it.
+    Mute {
+        name: String,
+    },
+    Unmute {
+        name: String,
+    },
 }
@@ -0,0 +1,84 @@
+const container = document.getElementById("lobbies");
+const cards = new Map();
+
+function card(id) {
+  if (cards.has(id)) {
+    return cards.get(id);
+  }
+  const link = document.createElement("a");
+  link.className = "lobby";
+  link.href = `/?lobby=${id}`;
+  const canvas = document.createElement("canvas");
+  const title = document.createElement("b");
+  title.innerText = `Lobby ${id}`;
+  const details = document.createElement("div");
+  link.append(canvas, title, details);
+  const entry = { link, canvas, details };
+  cards.set(id, entry);
+  return entry;
+}
+
+function drawThumbnail(canvas, thumbnail) {
+  const [width, height] = thumbnail.size;
+  canvas.width = width;
+  canvas.height = height;
+  const context = canvas.getContext("2d");
+  context.clearRect(0, 0, width, height);
+  thumbnail.map.forEach((piece, index) => {
+    const x = index % width;
+    const y = Math.floor(index / width);
+    if (piece == "Apple") {
+      context.fillStyle = "red";
+    } else if (piece.Snake != null) {
+      context.fillStyle = colorOf(piece.Snake);
+    } else if (piece.SnakeHead != null) {
+      context.fillStyle = "green";
+    } else {
+      return;
+    }
+    context.fillRect(x, y, 1, 1);
+  });
+}
+
+async function update() {
+  const response = await fetch("lobbies");
+  const lobbies = await response.json();
+  const live = new Set();
+  for (const lobby of lobbies) {
+    live.add(lobby.id);
+    const { link, canvas, details } = card(lobby.id);
+    drawThumbnail(canvas, lobby.thumbnail);
+    const speed = lobby.paused ? "paused" : `${(1000 / lobby.tick_ms).toFixed(1)} ticks/s`;
+    details.innerText =
+      `${lobby.players} playing, ${lobby.watchers} watching\n` +
+      `${lobby.map_size[0]}×${lobby.map_size[1]}, tick ${lobby.tick}, ${speed}`;
+    container.append(link);
+  }
+  for (const [id, { link }] of cards) {
+    if (!live.has(id)) {
+      link.remove();
+      cards.delete(id);
+    }
+  }
+  document.getElementById("empty").hidden = lobbies.length > 0;
+}
+async function updateReplays() {
+  const response = await fetch("replays");
+  const replays = await response.json();
+  const list = document.getElementById("replays");
+  list.replaceChildren();
+  for (const replay of replays.slice(0, 10)) {
+    const item = document.createElement("li");
+    const link = document.createElement("a");
+    link.href = `/?replay=${replay.id}`;
+    link.innerText = `Lobby ${replay.lobby}, ${replay.ticks} ticks`;
+    const winner = replay.standings[0];
+    item.append(link, winner ? `, won by ${winner.name}` : "");
+    list.append(item);
+  }
+}
+
+update();
+updateReplays();
+setInterval(update, 2000);
+setInterval(updateReplays, 10000);
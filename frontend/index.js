@@ -0,0 +1,607 @@
+const urlParams = new URLSearchParams(window.location.search);
+const lobbyId = urlParams.get("lobby") ?? 0;
+document.getElementById("lobby-id").innerText = `${lobbyId}`;
+
+document.getElementById("url-text").innerText = `wss://${window.window.location.host}/ws`;
+document.getElementById("url-text-2").innerText = `wss://${window.window.location.host}/ws?lobby=<id>`;
+const code = urlParams.get("code");
+const replayId = urlParams.get("replay");
+
+// lobbies created through POST /lobbies can be controlled with their host token
+const hostToken = urlParams.get("host");
+async function control(command) {
+  const response = await fetch(`admin/lobbies/${lobbyId}`, {
+    method: "POST",
+    headers: {
+      "content-type": "application/json",
+      "authorization": `Bearer ${hostToken}`,
+    },
+    body: JSON.stringify(command),
+  });
+  if (!response.ok) {
+    console.warn(await response.text());
+  }
+}
+if (hostToken) {
+  const controls = document.getElementById("controls");
+  controls.hidden = false;
+  for (const button of controls.querySelectorAll("[data-command]")) {
+    button.addEventListener("click", () => control(JSON.parse(button.dataset.command)));
+  }
+  document.getElementById("set-tick-rate").addEventListener("click", () => {
+    const ms = parseInt(document.getElementById("tick-ms").value);
+    control({ SetTickRate: { ms } });
+  });
+}
+const battlefield = document.getElementById("battlefield");
+const context = battlefield.getContext("2d");
+const scoreboard = document.getElementById("scoreboard");
+let mapSize = [0, 0];
+let apples = [];
+// the snakes of the last two updates by id, as [head, ...tail]
+let previous = new Map();
+let current = new Map();
+// the color and skin each snake's player picked, by id
+let looks = new Map();
+let updatedAt = performance.now();
+let tickMs = 1000;
+// the id of the snake played from this page
+let ownId = null;
+// what bots published with a Debug message, by snake id
+let annotations = new Map();
+const showDebug = document.getElementById("show-debug");
+// the watcher socket and the snake it inspects, click a snake on the scoreboard to pick one
+let watchSocket = null;
+let inspected = null;
+
+function inspectSnake(id, name) {
+  inspected = inspected === id ? null : id;
+  watchSocket.send(JSON.stringify({ Inspect: inspected }));
+  document.getElementById("inspector").hidden = inspected === null;
+  document.getElementById("inspector-title").innerText =
+    `What ${name} got and answered, newest first:`;
+  document.getElementById("inspector-log").replaceChildren();
+}
+
+function describe(event) {
+  if (event.Sent) {
+    const { tick_id, your_position, your_direction } = event.Sent;
+    return `#${tick_id} sent: at (${your_position}) going ${your_direction}`;
+  } else if (event.Skipped) {
+    return `#${event.Skipped.tick_id} skipped, the snake is behind`;
+  } else if (event.Answered) {
+    const { tick_id, turn, after_ms } = event.Answered;
+    return `#${tick_id} answered ${turn ?? "NoTurn"} after ${after_ms ?? "?"}ms`;
+  } else if (event.Applied) {
+    const { answered, turn, dropped } = event.Applied;
+    if (answered === null) {
+      return "no answer, kept going";
+    }
+    const lost = dropped > 0 ? `, dropped ${dropped} earlier answer${dropped > 1 ? "s" : ""}` : "";
+    return `applied ${turn ?? "NoTurn"} from #${answered}${lost}`;
+  } else if (event.Kicked) {
+    return `kicked: ${event.Kicked.reason}`;
+  }
+  return JSON.stringify(event);
+}
+
+function logInspect(event) {
+  const log = document.getElementById("inspector-log");
+  const item = document.createElement("li");
+  item.innerText = describe(event);
+  log.prepend(item);
+  while (log.children.length > 100) {
+    log.lastChild.remove();
+  }
+}
+
+// fits the whole map into the space left of the scoreboard and below the header
+function cellSize() {
+  const width = battlefield.parentElement.clientWidth - document.getElementById("side").offsetWidth - 16;
+  const height = window.innerHeight - battlefield.parentElement.offsetTop - 16;
+  const size = Math.min(width / mapSize[0], Math.max(height, 200) / mapSize[1]);
+  return Math.max(size, 1);
+}
+
+function resize() {
+  if (mapSize[0] == 0 || mapSize[1] == 0) {
+    return;
+  }
+  const cell = cellSize();
+  const ratio = window.devicePixelRatio || 1;
+  battlefield.style.width = `${cell * mapSize[0]}px`;
+  battlefield.style.height = `${cell * mapSize[1]}px`;
+  battlefield.width = Math.round(cell * mapSize[0] * ratio);
+  battlefield.height = Math.round(cell * mapSize[1] * ratio);
+}
+window.addEventListener("resize", resize);
+
+// neighbouring cells, which rules out the jump of a snake wrapping around the map
+function adjacent(a, b) {
+  return Math.abs(a[0] - b[0]) + Math.abs(a[1] - b[1]) <= 1;
+}
+
+function lerp(from, to, t) {
+  if (!from || !adjacent(from, to)) {
+    return to;
+  }
+  return [from[0] + (to[0] - from[0]) * t, from[1] + (to[1] - from[1]) * t];
+}
+
+// the head slides from its last cell to the new one while the tip of
+// the tail slides out of the cell it just left
+function interpolate(now, before, t) {
+  const points = [lerp(before?.[0], now[0], t), ...now.slice(1)];
+  const tip = before?.[before.length - 1];
+  const last = now[now.length - 1];
+  if (now.length > 1 && tip && adjacent(tip, last) && (tip[0] != last[0] || tip[1] != last[1])) {
+    points.push(lerp(tip, last, 1 - t));
+  }
+  return points;
+}
+
+// a line through the middle of the cells, split where it wraps around the map
+function polyline(points) {
+  const path = new Path2D();
+  for (let i = 0; i < points.length; i++) {
+    const [x, y] = points[i];
+    if (i == 0 || Math.abs(x - points[i - 1][0]) > 1 || Math.abs(y - points[i - 1][1]) > 1) {
+      path.moveTo(x + 0.5, y + 0.5);
+    } else {
+      path.lineTo(x + 0.5, y + 0.5);
+    }
+  }
+  return path;
+}
+
+function drawAnnotations() {
+  for (const [id, { list }] of annotations) {
+    const color = colorOf(id, looks.get(id));
+    for (const annotation of list) {
+      if (annotation.Path) {
+        context.strokeStyle = color;
+        context.lineWidth = 0.15;
+        context.setLineDash([0.2, 0.2]);
+        context.stroke(polyline(annotation.Path));
+        context.setLineDash([]);
+      } else if (annotation.Target) {
+        const [x, y] = annotation.Target;
+        context.strokeStyle = color;
+        context.lineWidth = 0.12;
+        context.beginPath();
+        context.arc(x + 0.5, y + 0.5, 0.45, 0, 2 * Math.PI);
+        context.stroke();
+      } else if (annotation.Label) {
+        const [x, y] = annotation.Label.at;
+        context.fillStyle = "black";
+        context.font = "0.5px sans-serif";
+        context.textAlign = "center";
+        context.fillText(annotation.Label.text, x + 0.5, y + 0.35);
+      }
+    }
+  }
+}
+
+function drawSkin(skin, body, points) {
+  if (skin == "Striped") {
+    context.strokeStyle = "rgba(0, 0, 0, 0.3)";
+    context.setLineDash([0.3, 0.3]);
+    context.stroke(body);
+    context.setLineDash([]);
+  } else if (skin == "Dotted") {
+    context.fillStyle = "rgba(255, 255, 255, 0.8)";
+    for (const [x, y] of points.slice(1)) {
+      context.beginPath();
+      context.arc(x + 0.5, y + 0.5, 0.12, 0, 2 * Math.PI);
+      context.fill();
+    }
+  }
+}
+
+function draw(time) {
+  requestAnimationFrame(draw);
+  if (mapSize[0] == 0 || mapSize[1] == 0) {
+    return;
+  }
+  const t = Math.min((time - updatedAt) / tickMs, 1);
+  const scale = battlefield.width / mapSize[0];
+  context.setTransform(scale, 0, 0, scale, 0, 0);
+  context.clearRect(0, 0, mapSize[0], mapSize[1]);
+
+  if (scale >= 6) {
+    context.fillStyle = "lightgray";
+    for (let y = 0; y < mapSize[1]; y++) {
+      for (let x = 0; x < mapSize[0]; x++) {
+        context.fillRect(x + 0.05, y + 0.05, 0.9, 0.9);
+      }
+    }
+  }
+
+  context.fillStyle = "red";
+  for (const [x, y] of apples) {
+    context.beginPath();
+    context.arc(x + 0.5, y + 0.5, 0.35, 0, 2 * Math.PI);
+    context.fill();
+  }
+
+  context.lineCap = "round";
+  context.lineJoin = "round";
+  for (const [id, snake] of current) {
+    const points = interpolate(snake, previous.get(id), t);
+    const color = colorOf(id, looks.get(id));
+    const body = polyline(points);
+    // your own snake gets an outline when playing
+    if (id === ownId) {
+      context.strokeStyle = "black";
+      context.lineWidth = 0.85;
+      context.stroke(body);
+    }
+    context.strokeStyle = color;
+    context.lineWidth = 0.6;
+    context.stroke(body);
+    drawSkin(looks.get(id)?.skin, body, points);
+
+    const [x, y] = points[0];
+    context.fillStyle = color;
+    context.strokeStyle = id === ownId ? "black" : "green";
+    context.lineWidth = id === ownId ? 0.15 : 0.1;
+    context.beginPath();
+    context.arc(x + 0.5, y + 0.5, 0.42, 0, 2 * Math.PI);
+    context.fill();
+    context.stroke();
+  }
+
+  if (showDebug.checked) {
+    drawAnnotations();
+  }
+}
+requestAnimationFrame(draw);
+
+// draws an update, sliding the snakes over from the last one for `ms` when animated
+function show(data, animate, ms) {
+  if (mapSize[0] != data.map_size[0] || mapSize[1] != data.map_size[1]) {
+    mapSize = data.map_size;
+    previous = new Map();
+    current = new Map();
+    resize();
+  }
+
+  tickMs = ms;
+  updatedAt = performance.now();
+  apples = [];
+  data.map.forEach((piece, index) => {
+    if (piece == "Apple") {
+      apples.push([index % mapSize[0], Math.floor(index / mapSize[0])]);
+    }
+  });
+  previous = animate ? current : new Map();
+  current = new Map(data.clients.map(c => [c.id, [c.position, ...c.tail]]));
+  looks = new Map(data.clients.map(c => [c.id, c.appearance]));
+  // annotations are kept for the tick they were made on and the one after
+  for (const [id, entry] of annotations) {
+    entry.age += 1;
+    if (entry.age > 1 || !current.has(id)) {
+      annotations.delete(id);
+    }
+  }
+
+  let players = [];
+  for (player of data.clients) {
+    players.push(player);
+  }
+  players.sort((a, b) => b.tail_len - a.tail_len);
+  scoreboard.replaceChildren();
+  for (const player of players) {
+    // names come from the bots, only ever render them as text
+    const brick = document.createElement("div");
+    brick.className = "player-brick";
+    brick.style.backgroundColor = colorOf(player.id, player.appearance);
+    const line = document.createElement("div");
+    if (player.id === ownId) {
+      line.style.fontWeight = "bold";
+    }
+    if (player.id === inspected) {
+      line.style.textDecoration = "underline";
+    }
+    if (watchSocket) {
+      line.addEventListener("click", () => inspectSnake(player.id, player.name));
+    }
+    const rating = player.rating != null ? ` | ${Math.round(player.rating)}` : "";
+    line.append(
+      brick,
+      ` ${player.name}: ${player.tail_len} | ${player.death} | ${player.latency_ms ?? "?"}ms${rating}`,
+    );
+    scoreboard.append(line);
+  }
+}
+
+// spectators can talk about the match, the name is remembered for next time
+function setupChat(socket) {
+  const chat = document.getElementById("chat");
+  const name = document.getElementById("chat-name");
+  const input = document.getElementById("chat-text");
+  const send = (text) => socket.send(JSON.stringify({ Chat: text }));
+  const setName = () => {
+    localStorage.setItem("chat-name", name.value);
+    if (name.value.trim()) {
+      socket.send(JSON.stringify({ SetName: name.value }));
+    }
+  };
+  name.value = localStorage.getItem("chat-name") ?? "";
+  setName();
+  name.addEventListener("change", setName);
+  document.getElementById("chat-form").addEventListener("submit", (event) => {
+    event.preventDefault();
+    if (input.value.trim()) {
+      send(input.value);
+      input.value = "";
+    }
+  });
+  for (const emote of document.querySelectorAll("#emotes button")) {
+    emote.addEventListener("click", () => send(emote.innerText));
+  }
+  chat.hidden = false;
+}
+
+// a message without a name comes from the server
+function logChat(name, text) {
+  const log = document.getElementById("chat-log");
+  const item = document.createElement("li");
+  if (name === null) {
+    const note = document.createElement("i");
+    note.innerText = text;
+    item.append(note);
+  } else {
+    const who = document.createElement("b");
+    who.innerText = `${name}: `;
+    item.append(who, text);
+  }
+  log.append(item);
+  while (log.children.length > 200) {
+    log.firstChild.remove();
+  }
+  log.scrollTop = log.scrollHeight;
+}
+
+function watch() {
+  const socket = new WebSocket(`watch?v=2&lobby=${lobbyId}${code ? `&code=${encodeURIComponent(code)}` : ""}`);
+  let lastUpdate = performance.now();
+  socket.addEventListener("open", () => {
+    watchSocket = socket;
+    setupChat(socket);
+  });
+  socket.addEventListener("message", (event) => {
+    const msg = JSON.parse(event.data);
+    if (msg.Announcement) {
+      document.getElementById("announcement").innerText = msg.Announcement.text;
+      return;
+    }
+    if (msg.Inspect) {
+      logInspect(msg.Inspect);
+      return;
+    }
+    if (msg.Chat) {
+      logChat(msg.Chat.name, msg.Chat.text);
+      return;
+    }
+    if (msg.ChatRefused) {
+      logChat(null, msg.ChatRefused.reason);
+      return;
+    }
+    if (msg.Debug) {
+      annotations.set(msg.Debug.snake, { age: 0, list: msg.Debug.annotations });
+      return;
+    }
+    const now = performance.now();
+    // animate over about as long as the lobby takes per tick
+    show(msg.Update, true, Math.min(Math.max(now - lastUpdate, 50), 1000));
+    lastUpdate = now;
+  });
+}
+
+// plays back a finished match from /replays, a few ticks per second at 1×
+async function replay(id) {
+  const frameMs = 200;
+  const response = await fetch(`replays/${encodeURIComponent(id)}`);
+  if (!response.ok) {
+    document.getElementById("announcement").innerText = await response.text();
+    return;
+  }
+  const { lobby, standings, frames } = await response.json();
+  document.getElementById("lobby-id").innerText = `${lobby}, replay ${id}`;
+  document.getElementById("announcement").innerText = "Final standings: " + standings
+    .map(s => `${s.place}. ${s.name} (${s.tail_len})`)
+    .join(", ");
+
+  const controls = document.getElementById("replay");
+  const play = document.getElementById("replay-play");
+  const speed = document.getElementById("replay-speed");
+  const timeline = document.getElementById("replay-timeline");
+  const label = document.getElementById("replay-tick");
+  controls.hidden = false;
+  timeline.max = frames.length - 1;
+  let frame = 0;
+  let playing = false;
+  let timer = null;
+
+  const delay = () => frameMs / parseFloat(speed.value);
+  function seek(index, animate) {
+    frame = Math.min(Math.max(index, 0), frames.length - 1);
+    timeline.value = frame;
+    label.innerText = `tick ${frame + 1} / ${frames.length}`;
+    show(frames[frame], animate, delay());
+  }
+  function schedule() {
+    clearTimeout(timer);
+    if (!playing) {
+      return;
+    }
+    timer = setTimeout(() => {
+      if (frame + 1 >= frames.length) {
+        setPlaying(false);
+        return;
+      }
+      seek(frame + 1, true);
+      schedule();
+    }, delay());
+  }
+  function setPlaying(value) {
+    playing = value;
+    play.innerText = playing ? "Pause" : "Play";
+    schedule();
+  }
+
+  play.addEventListener("click", () => {
+    if (!playing && frame + 1 >= frames.length) {
+      seek(0, false);
+    }
+    setPlaying(!playing);
+  });
+  document.getElementById("replay-back").addEventListener("click", () => {
+    setPlaying(false);
+    seek(frame - 1, false);
+  });
+  document.getElementById("replay-forward").addEventListener("click", () => {
+    setPlaying(false);
+    seek(frame + 1, true);
+  });
+  timeline.addEventListener("input", () => seek(parseInt(timeline.value), false));
+  speed.addEventListener("change", schedule);
+  if (frames.length > 0) {
+    seek(0, false);
+  }
+}
+
+// plays from the browser, the keys pick the direction to go in and every
+// tick is answered with whatever turn gets there
+function play(name) {
+  const params = new URLSearchParams({ lobby: lobbyId });
+  for (const key of ["code", "token"]) {
+    if (urlParams.has(key)) {
+      params.set(key, urlParams.get(key));
+    }
+  }
+  const socket = new WebSocket(`ws?${params}`);
+  const clockwise = { Left: "Up", Up: "Right", Right: "Down", Down: "Left" };
+  const keys = {
+    ArrowUp: "Up", KeyW: "Up",
+    ArrowLeft: "Left", KeyA: "Left",
+    ArrowDown: "Down", KeyS: "Down",
+    ArrowRight: "Right", KeyD: "Right",
+  };
+  let tick = null;
+  let wanted = null;
+  // the answer last sent, answering a tick too often gets the snake kicked
+  let sent = null;
+
+  function answer() {
+    if (!tick || socket.readyState != WebSocket.OPEN) {
+      return;
+    }
+    const direction = tick.your_direction;
+    let msg = { NoTurn: tick.tick_id };
+    if (wanted == clockwise[direction]) {
+      msg = { Turn: [tick.tick_id, "Clockwise"] };
+    } else if (clockwise[wanted] == direction) {
+      msg = { Turn: [tick.tick_id, "CounterClockwise"] };
+    }
+    const data = JSON.stringify(msg);
+    if (data != sent) {
+      sent = data;
+      socket.send(data);
+    }
+  }
+
+  socket.addEventListener("open", () => {
+    const color = urlParams.get("color");
+    const skin = urlParams.get("skin") ?? "Plain";
+    socket.send(JSON.stringify({ Appearance: { color, skin } }));
+    socket.send(JSON.stringify({ SetName: name }));
+  });
+  socket.addEventListener("message", (event) => {
+    const msg = JSON.parse(event.data);
+    const announcement = document.getElementById("announcement");
+    if (msg.Tick) {
+      tick = msg.Tick;
+      const [x, y] = tick.your_position;
+      ownId = tick.map[x + y * tick.map_size[0]]?.SnakeHead ?? ownId;
+      answer();
+    } else if (msg.Kicked) {
+      announcement.innerText = `You were kicked: ${msg.Kicked.reason}`;
+    } else if (msg.LobbyFull) {
+      announcement.innerText = `The lobby is full, it takes ${msg.LobbyFull.max_players} players.`;
+    } else if (msg.MatchEnded) {
+      announcement.innerText = "Match over: " + msg.MatchEnded.standings
+        .map(s => `${s.place}. ${s.name} (${s.tail_len})`)
+        .join(", ");
+    } else if (msg.Announcement) {
+      announcement.innerText = msg.Announcement.text;
+    } else if (msg.ServerShuttingDown) {
+      announcement.innerText = `The server is shutting down in ${msg.ServerShuttingDown.seconds_left}s.`;
+    }
+  });
+  socket.addEventListener("close", () => {
+    ownId = null;
+    document.getElementById("play-help").hidden = true;
+  });
+
+  document.addEventListener("keydown", (event) => {
+    const direction = keys[event.code];
+    if (!direction || event.target instanceof HTMLInputElement) {
+      return;
+    }
+    event.preventDefault();
+    if (event.repeat) {
+      return;
+    }
+    wanted = direction;
+    // only the last answer per tick counts, so this replaces the one already sent
+    answer();
+  });
+  document.getElementById("play-help").hidden = false;
+}
+
+document.querySelector("#play-form [name=lobby]").value = lobbyId;
+for (const key of ["color", "skin"]) {
+  if (urlParams.has(key)) {
+    document.querySelector(`#play-form [name=${key}]`).value = urlParams.get(key);
+  }
+}
+const playName = urlParams.get("play");
+if (replayId) {
+  document.getElementById("play-form").hidden = true;
+  replay(replayId);
+} else {
+  watch();
+  if (playName) {
+    document.getElementById("play-form").hidden = true;
+    play(playName);
+  }
+}
+
+const leaderboard = document.querySelector("#leaderboard tbody");
+async function updateLeaderboard() {
+  const response = await fetch("leaderboard");
+  const entries = await response.json();
+  leaderboard.innerHTML = "";
+  for (const entry of entries.slice(0, 20)) {
+    const row = document.createElement("tr");
+    for (const value of [
+      entry.name,
+      entry.sessions,
+      entry.matches,
+      entry.max_length,
+      entry.apples,
+      entry.kills,
+      entry.deaths,
+      entry.avg_survival_ticks.toFixed(1),
+    ]) {
+      const cell = document.createElement("td");
+      cell.innerText = value;
+      row.appendChild(cell);
+    }
+    leaderboard.appendChild(row);
+  }
+}
+updateLeaderboard();
+setInterval(updateLeaderboard, 10000);
@@ -0,0 +1,10 @@
+// the colors snakes get when their player didn't pick one, shared by every page
+const colors = [
+  "greenyellow", "yellow", "red", "orange", "teal", "purple",
+  "olive", "navy", "aqua", "coral", "gray", "hotpink",
+];
+
+// the color a player picked when joining, or one from the palette
+function colorOf(id, appearance) {
+  return appearance?.color ?? colors[id % colors.length];
+}
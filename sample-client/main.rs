@@ -2,7 +2,7 @@ use crate::client_ext::ClientExt;
 use async_tungstenite::tokio::connect_async;
 use futures::StreamExt;
 use pathfinding::directed::dijkstra::dijkstra;
-use snakes_shared::{ClientMessage, Direction, MapPiece, ServerMessage, TurnDirection};
+use snakes_shared::{Annotation, ClientMessage, Direction, MapPiece, ServerMessage, TurnDirection};
 use std::env::args;
 use tungstenite::Message;
 
@@ -18,12 +18,13 @@ fn get_map(map: Vec<MapPiece>, map_size: (usize, usize)) -> Vec<Vec<MapPiece>> {
     }
     mv
 }
+/// Picks the turn towards the closest apple, along with the path it plans to take there.
 fn path_to_apple(
     map: &[Vec<MapPiece>],
     (m_x, m_y): (usize, usize),
     (p_x, p_y): (usize, usize),
     your_direction: Direction,
-) -> Option<TurnDirection> {
+) -> (Option<TurnDirection>, Vec<(usize, usize)>) {
     let apples = map
         .iter()
         .enumerate()
@@ -115,7 +116,8 @@ fn path_to_apple(
             shortest_path = (dir, cost)
         }
     }
-    if let Some((d, _, _)) = shortest_path.0.get(1) {
+    let path = shortest_path.0.iter().map(|&(_, x, y)| (x, y)).collect();
+    let turn = if let Some((d, _, _)) = shortest_path.0.get(1) {
         use Direction::*;
         use TurnDirection::*;
 
@@ -138,7 +140,8 @@ fn path_to_apple(
         }
     } else {
         None
-    }
+    };
+    (turn, path)
 }
 
 async fn game_client(lobby: String, name: String) -> anyhow::Result<()> {
@@ -183,12 +186,22 @@ async fn game_client(lobby: String, name: String) -> anyhow::Result<()> {
                 //     }
                 //     println!();
                 // }
-                let path = path_to_apple(&map, map_size, your_position, your_direction);
-                if let Some(dir) = path {
+                let (turn, path) = path_to_apple(&map, map_size, your_position, your_direction);
+                if let Some(dir) = turn {
                     writer.msg(ClientMessage::Turn(tick_id, dir)).await?;
                 } else {
                     writer.msg(ClientMessage::NoTurn(tick_id)).await?;
                 }
+                // lets watchers see where the snake is headed
+                if let Some(&apple) = path.last() {
+                    let annotations = vec![Annotation::Path(path), Annotation::Target(apple)];
+                    writer
+                        .msg(ClientMessage::Debug {
+                            tick_id,
+                            annotations,
+                        })
+                        .await?;
+                }
 
                 // if rand::random::<bool>() {
                 //     let dir = match rand::random::<bool>() {
@@ -198,9 +211,32 @@ async fn game_client(lobby: String, name: String) -> anyhow::Result<()> {
                 //     writer.msg(ClientMessage::Turn(dir)).await?;
                 // }
             }
+            ServerMessage::ServerShuttingDown { seconds_left } => {
+                println!("{name}: server shutting down in {seconds_left}s");
+            }
+            ServerMessage::MatchFound { lobby } => {
+                println!("{name}: playing in lobby {lobby}");
+            }
+            ServerMessage::MatchEnded { standings } => {
+                if let Some(me) = standings.iter().find(|s| s.name == name) {
+                    println!("{name}: match over, finished #{}", me.place);
+                }
+            }
+            ServerMessage::Announcement { text } => {
+                println!("{name}: announcement: {text}");
+            }
+            ServerMessage::LobbyFull { max_players } => {
+                println!("{name}: the lobby is full, it only takes {max_players} players");
+                break;
+            }
+            ServerMessage::Kicked { reason } => {
+                println!("{name} got kicked: {reason}");
+                break;
+            }
         }
     }
-    writer.close(None).await?;
+    // the server may already have closed the connection
+    _ = writer.close(None).await;
     Ok(())
 }
 
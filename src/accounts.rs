@@ -0,0 +1,191 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{config::CONFIG, game::sanitize_name, storage};
+
+/// Names reserved to a token, kept in `SNAKES_TOKEN_FILE`.
+///
+/// Only a hash of every token is stored, the token itself is printed once when issued.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Accounts {
+    accounts: BTreeMap<String, Account>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Account {
+    token_sha256: String,
+}
+
+fn hash(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+impl Accounts {
+    pub async fn load(path: &Path) -> anyhow::Result<Self> {
+        let Some(json) = storage::read_optional(path).await? else {
+            return Ok(Self::default());
+        };
+        serde_json::from_slice(&json).context(format!("invalid {}", path.display()))
+    }
+
+    pub async fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let json = serde_json::to_vec_pretty(self).context("failed encoding accounts")?;
+        storage::write_atomic(path, &json).await
+    }
+
+    /// Reserves `name` and returns its new token, replacing any previous one.
+    ///
+    /// Players are matched by their sanitized name, so names that sanitizing would change
+    /// are refused rather than reserved for nobody.
+    pub fn issue(&mut self, name: &str) -> anyhow::Result<String> {
+        let sanitized = sanitize_name(name);
+        if sanitized != name {
+            anyhow::bail!(
+                "{name:?} can't be registered, players using it show up as {sanitized:?}"
+            );
+        }
+        let token = rand::random::<[u8; 32]>()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>();
+        self.accounts.insert(
+            name.to_string(),
+            Account {
+                token_sha256: hash(&token),
+            },
+        );
+        Ok(token)
+    }
+
+    pub fn revoke(&mut self, name: &str) -> bool {
+        self.accounts.remove(name).is_some() || self.accounts.remove(&sanitize_name(name)).is_some()
+    }
+
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.accounts.contains_key(name)
+    }
+
+    /// Checks that `name` may be used by someone holding `token`,
+    /// returning whether it is a registered name.
+    pub fn verify(&self, name: &str, token: Option<&str>) -> Result<bool, String> {
+        match token {
+            Some(token) => {
+                let hash = hash(token);
+                match self.accounts.get(name) {
+                    Some(account) if account.token_sha256 == hash => Ok(true),
+                    _ => Err(format!("token is not valid for {name}")),
+                }
+            }
+            None => {
+                let lower = sanitize_name(name).to_lowercase();
+                let reserved = self
+                    .accounts
+                    .keys()
+                    .find(|n| sanitize_name(n).to_lowercase() == lower);
+                match reserved {
+                    Some(reserved) => Err(format!(
+                        "{reserved} is a registered name, authenticate with its token"
+                    )),
+                    None => Ok(false),
+                }
+            }
+        }
+    }
+}
+
+/// Checks a name against the token file, which is read again every time so
+/// tokens issued or revoked through the CLI apply right away.
+pub async fn verify(name: &str, token: Option<&str>) -> Result<bool, String> {
+    match Accounts::load(&CONFIG.token_file).await {
+        Ok(accounts) => accounts.verify(name, token),
+        Err(e) => {
+            error!("failed to load accounts: {e:#}");
+            Err("accounts are unavailable, try again later".to_string())
+        }
+    }
+}
+
+/// `angry-snakes token ...`, returns the exit code.
+#[allow(clippy::print_stderr)]
+pub async fn cli(args: &[String]) -> i32 {
+    match run_cli(args).await {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{e:#}");
+            1
+        }
+    }
+}
+
+#[allow(clippy::print_stdout)]
+async fn run_cli(args: &[String]) -> anyhow::Result<()> {
+    let path = &CONFIG.token_file;
+    let mut accounts = Accounts::load(path).await?;
+    match args {
+        [cmd, name] if cmd == "issue" => {
+            let token = accounts.issue(name)?;
+            accounts.save(path).await?;
+            println!("{token}");
+        }
+        [cmd, name] if cmd == "revoke" => {
+            if !accounts.revoke(name) {
+                anyhow::bail!("{name} has no token");
+            }
+            accounts.save(path).await?;
+            println!("revoked the token for {name}");
+        }
+        [cmd] if cmd == "list" => {
+            for name in accounts.accounts.keys() {
+                println!("{name}");
+            }
+        }
+        _ => anyhow::bail!("usage: angry-snakes token (issue <name> | revoke <name> | list)"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Accounts;
+
+    #[test]
+    fn verify() {
+        let mut accounts = Accounts::default();
+        let token = accounts.issue("Alfa").expect("failed issuing");
+        assert_eq!(accounts.verify("Alfa", Some(&token)), Ok(true));
+        assert!(accounts.verify("Alfa", None).is_err());
+        assert!(accounts.verify("alfa", None).is_err());
+        for invisible in ['\u{200b}', '\u{2060}', '\u{feff}', '\u{202e}'] {
+            assert!(accounts.verify(&format!("Alfa{invisible}"), None).is_err());
+            assert!(accounts.verify(&format!("a{invisible}lfa"), None).is_err());
+        }
+        assert!(accounts.verify("Alfa", Some("guess")).is_err());
+        assert!(accounts.verify("Bravo", Some(&token)).is_err());
+        assert_eq!(accounts.verify("Bravo", None), Ok(false));
+
+        let new_token = accounts.issue("Alfa").expect("failed issuing");
+        assert!(accounts.verify("Alfa", Some(&token)).is_err());
+        assert_eq!(accounts.verify("Alfa", Some(&new_token)), Ok(true));
+
+        assert!(accounts.revoke("Alfa"));
+        assert_eq!(accounts.verify("Alfa", None), Ok(false));
+    }
+
+    #[test]
+    fn unusable_names() {
+        let mut accounts = Accounts::default();
+        assert!(accounts.issue("O'Brien").is_err());
+        assert!(accounts.issue("<Alfa>").is_err());
+        assert!(accounts.issue(&"x".repeat(33)).is_err());
+        assert!(!accounts.is_registered("OBrien"));
+        assert!(accounts.issue("Ålfa 🐍").is_ok());
+        assert!(accounts.revoke("<Ålfa 🐍>"));
+    }
+}
@@ -0,0 +1,156 @@
+use axum::{
+    Json,
+    extract::Path as UrlPath,
+    http::{HeaderMap, StatusCode, header::AUTHORIZATION},
+};
+use snakes_shared::AdminCommand;
+use tokio::sync::oneshot;
+
+use crate::{ClientUpdate, STATE, config::CONFIG, game::FASTEST_TICK};
+
+fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+/// Whether the request carries `SNAKES_ADMIN_TOKEN`.
+pub fn is_admin(headers: &HeaderMap) -> bool {
+    let token = bearer(headers);
+    token.is_some() && CONFIG.admin_token.as_deref() == token
+}
+
+/// What the host of a lobby may do, which is enough to debug a bot.
+fn host_may(command: &AdminCommand) -> bool {
+    matches!(
+        command,
+        AdminCommand::Pause
+            | AdminCommand::Resume
+            | AdminCommand::Step
+            | AdminCommand::SetTickRate { .. }
+            | AdminCommand::Mute { .. }
+            | AdminCommand::Unmute { .. }
+    )
+}
+
+/// `POST /admin/lobbies/{id}`, needs `SNAKES_ADMIN_TOKEN` or the host token of the lobby
+/// as a bearer token.
+pub async fn admin_command(
+    UrlPath(lobby): UrlPath<usize>,
+    headers: HeaderMap,
+    Json(mut command): Json<AdminCommand>,
+) -> (StatusCode, String) {
+    let token = bearer(&headers);
+    let admin = is_admin(&headers);
+    let info = STATE.read().await.get(&lobby).cloned();
+    let host = info
+        .as_ref()
+        .is_some_and(|info| token.is_some() && info.access.host_token.as_deref() == token);
+    if !admin && !host {
+        return (StatusCode::UNAUTHORIZED, "wrong admin token".to_string());
+    }
+    if !admin && !host_may(&command) {
+        return (
+            StatusCode::FORBIDDEN,
+            "hosts may not issue that command".to_string(),
+        );
+    }
+    // hosts may slow their lobby down, but not make the server tick any faster
+    if !admin && let AdminCommand::SetTickRate { ms } = &mut command {
+        *ms = (*ms).max(FASTEST_TICK.as_millis() as u64);
+    }
+    let Some(info) = info else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("lobby {lobby} is not running"),
+        );
+    };
+    let (send, recv) = oneshot::channel();
+    if info
+        .client_update
+        .send(ClientUpdate::Admin(command, send))
+        .await
+        .is_err()
+    {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("lobby {lobby} is not running"),
+        );
+    }
+    match recv.await {
+        Ok(Ok(())) => (StatusCode::OK, "done".to_string()),
+        Ok(Err(e)) => (StatusCode::BAD_REQUEST, e),
+        Err(_) => (
+            StatusCode::NOT_FOUND,
+            format!("lobby {lobby} is not running"),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Json,
+        extract::Path as UrlPath,
+        http::{HeaderMap, HeaderValue, StatusCode, header::AUTHORIZATION},
+    };
+    use snakes_shared::AdminCommand;
+
+    use super::{admin_command, bearer};
+    use crate::{STATE, create_lobby, game::LobbySettings, lobbies::Access};
+
+    #[test]
+    fn bearer_token() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(bearer(&headers), None);
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Basic abc"));
+        assert_eq!(bearer(&headers), None);
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer abc"));
+        assert_eq!(bearer(&headers), Some("abc"));
+    }
+
+    #[tokio::test]
+    async fn hosts_are_limited() {
+        let lobby = create_lobby(LobbySettings {
+            access: Access {
+                host_token: Some("host".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .await;
+        let mut headers = HeaderMap::new();
+        let (status, _) =
+            admin_command(UrlPath(lobby), headers.clone(), Json(AdminCommand::Pause)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer host"));
+        let (status, _) = admin_command(
+            UrlPath(lobby),
+            headers.clone(),
+            Json(AdminCommand::ResetScores),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) =
+            admin_command(UrlPath(lobby), headers.clone(), Json(AdminCommand::Pause)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = admin_command(
+            UrlPath(lobby),
+            headers,
+            Json(AdminCommand::SetTickRate { ms: 1 }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let mut summary = STATE.read().await[&lobby].summary.clone();
+        let tick_ms = summary
+            .wait_for(|feed| feed.tick_ms != 1000)
+            .await
+            .expect("lobby closed")
+            .tick_ms;
+        assert_eq!(tick_ms, 200);
+    }
+}
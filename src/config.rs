@@ -0,0 +1,84 @@
+use std::{env, path::PathBuf, str::FromStr, sync::LazyLock, time::Duration};
+
+use anyhow::Context;
+
+use crate::lag::LagPolicy;
+
+/// Server wide settings, read once from the environment.
+pub static CONFIG: LazyLock<Config> =
+    LazyLock::new(|| Config::from_env().expect("invalid configuration"));
+
+#[derive(Debug)]
+pub struct Config {
+    pub lag_policy: LagPolicy,
+    /// How many commands a client may send in a single tick before being kicked.
+    pub flood_limit: usize,
+    /// How many messages may be waiting for a player before it is disconnected.
+    pub player_buffer: usize,
+    /// How many updates may be waiting for a watcher before the oldest are dropped.
+    pub watcher_buffer: usize,
+    /// How long running lobbies keep going after a shutdown signal.
+    pub shutdown_grace: Duration,
+    /// Where lobbies are saved when they close, snapshots are disabled when unset.
+    pub snapshot_dir: Option<PathBuf>,
+    /// Where player statistics are kept, they only live in memory when unset.
+    pub stats_file: Option<PathBuf>,
+    /// Where registered names and their token hashes are kept.
+    pub token_file: PathBuf,
+    /// Default length of a match in ticks, lobbies play one endless match when unset.
+    pub match_ticks: Option<usize>,
+    /// How many players a lobby lets in unless created with another limit.
+    pub max_players: usize,
+    /// Bearer token allowing every command on `/admin`, lobby hosts may only use a few.
+    pub admin_token: Option<String>,
+    /// Players per ranked match.
+    pub queue_size: usize,
+    /// How many lobbies made through `POST /lobbies` may run at once.
+    pub max_created_lobbies: usize,
+    /// Lowercase words starred out of chat messages.
+    pub chat_blocklist: Vec<String>,
+}
+
+impl Config {
+    fn from_env() -> anyhow::Result<Self> {
+        Ok(Self {
+            lag_policy: var("SNAKES_LAG_POLICY")?.unwrap_or_default(),
+            flood_limit: var("SNAKES_FLOOD_LIMIT")?.unwrap_or(20),
+            player_buffer: var("SNAKES_PLAYER_BUFFER")?.unwrap_or(16),
+            watcher_buffer: var("SNAKES_WATCHER_BUFFER")?.unwrap_or(8),
+            shutdown_grace: Duration::from_secs(var("SNAKES_SHUTDOWN_GRACE")?.unwrap_or(5)),
+            snapshot_dir: var("SNAKES_SNAPSHOT_DIR")?,
+            stats_file: var("SNAKES_STATS_FILE")?,
+            token_file: var("SNAKES_TOKEN_FILE")?.unwrap_or_else(|| "tokens.json".into()),
+            match_ticks: var("SNAKES_MATCH_TICKS")?,
+            max_players: var("SNAKES_MAX_PLAYERS")?.unwrap_or(16),
+            admin_token: var("SNAKES_ADMIN_TOKEN")?,
+            queue_size: var("SNAKES_QUEUE_SIZE")?.unwrap_or(2).max(2),
+            max_created_lobbies: var("SNAKES_MAX_CREATED_LOBBIES")?.unwrap_or(64),
+            chat_blocklist: var::<String>("SNAKES_CHAT_BLOCKLIST")?
+                .map(|list| {
+                    list.split(',')
+                        .map(|word| word.trim().to_lowercase())
+                        .filter(|word| !word.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        })
+    }
+}
+
+fn var<T>(name: &str) -> anyhow::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Into<anyhow::Error>,
+{
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(Into::into)
+            .context(format!("failed parsing {name}={value}")),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(e) => Err(e).context(format!("failed reading {name}")),
+    }
+}
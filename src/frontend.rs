@@ -1,47 +1,134 @@
 use axum::{
-    http::{HeaderMap, HeaderValue, header::CONTENT_TYPE},
-    response::Html as AxumHtml,
+    extract::Path,
+    http::{
+        HeaderMap, HeaderValue, StatusCode,
+        header::{
+            CACHE_CONTROL, CONTENT_SECURITY_POLICY, CONTENT_TYPE, ETAG, IF_NONE_MATCH,
+            X_CONTENT_TYPE_OPTIONS,
+        },
+    },
+    response::{IntoResponse, Response},
 };
-use std::sync::LazyLock;
-
-#[cfg(debug_assertions)]
-type Html = AxumHtml<String>;
-#[cfg(not(debug_assertions))]
-type Html = AxumHtml<&'static str>;
-
-#[cfg(debug_assertions)]
-pub async fn index() -> Html {
-    #[allow(clippy::unwrap_used)]
-    AxumHtml(
-        tokio::fs::read_to_string("frontend/index.html")
-            .await
-            .unwrap(),
-    )
-}
-
-#[cfg(not(debug_assertions))]
-pub async fn index() -> Html {
-    AxumHtml(include_str!("../frontend/index.html"))
-}
-
-#[cfg(debug_assertions)]
-pub async fn serve_schema() -> (HeaderMap, String) {
-    static SCHEMA: LazyLock<(HeaderMap, String)> = LazyLock::new(|| {
-        let string =
-            std::fs::read_to_string("frontend/schema.json").expect("failed to load schema");
-        let mut map = HeaderMap::new();
-        map.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-        (map, string)
-    });
-    SCHEMA.clone()
-}
-
-#[cfg(not(debug_assertions))]
-pub async fn serve_schema() -> (HeaderMap, &'static str) {
-    static SCHEMA: LazyLock<(HeaderMap, &'static str)> = LazyLock::new(|| {
-        let mut map = HeaderMap::new();
-        map.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-        (map, include_str!("../frontend/schema.json"))
-    });
-    SCHEMA.clone()
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+
+/// Pages may only load scripts and styles from `/static`, so markup sneaking into them
+/// can't execute anything.
+const CONTENT_SECURITY_POLICY_VALUE: &str = "default-src 'self'; script-src 'self'; \
+    style-src 'self'; img-src 'self' data:; object-src 'none'; base-uri 'none'; \
+    frame-ancestors 'none'";
+
+/// A file from `frontend/` that is served as is.
+struct Asset {
+    file: &'static str,
+    content_type: &'static str,
+    #[cfg(not(debug_assertions))]
+    body: &'static str,
+}
+
+/// Release builds bake the files in, debug builds read them from disk so they can be
+/// edited live.
+macro_rules! assets {
+    ($($file:literal => $content_type:literal),* $(,)?) => {
+        &[$(Asset {
+            file: $file,
+            content_type: $content_type,
+            #[cfg(not(debug_assertions))]
+            body: include_str!(concat!("../frontend/", $file)),
+        }),*]
+    };
+}
+
+static ASSETS: &[Asset] = assets![
+    "index.html" => "text/html; charset=utf-8",
+    "dashboard.html" => "text/html; charset=utf-8",
+    "index.js" => "text/javascript; charset=utf-8",
+    "dashboard.js" => "text/javascript; charset=utf-8",
+    "palette.js" => "text/javascript; charset=utf-8",
+    "index.css" => "text/css; charset=utf-8",
+    "dashboard.css" => "text/css; charset=utf-8",
+    "schema.json" => "application/json",
+];
+
+impl Asset {
+    #[cfg(debug_assertions)]
+    async fn body(&self) -> std::io::Result<Cow<'static, str>> {
+        let body = tokio::fs::read_to_string(format!("frontend/{}", self.file)).await?;
+        Ok(Cow::Owned(body))
+    }
+
+    #[cfg(not(debug_assertions))]
+    async fn body(&self) -> std::io::Result<Cow<'static, str>> {
+        Ok(Cow::Borrowed(self.body))
+    }
+}
+
+/// Whether the client's cached copy, going by its `If-None-Match`, is still current.
+fn is_fresh(request: &HeaderMap, etag: &str) -> bool {
+    request
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == etag || tag == "*")
+}
+
+/// Serves `file` with an `ETag`, browsers have to check back each time but get an empty
+/// `304 Not Modified` while their copy is current.
+async fn serve(file: &str, request: &HeaderMap) -> Response {
+    let Some(asset) = ASSETS.iter().find(|asset| asset.file == file) else {
+        return (StatusCode::NOT_FOUND, format!("there is no {file}")).into_response();
+    };
+    let body = match asset.body().await {
+        Ok(body) => body,
+        Err(e) => {
+            error!("failed to load {file}: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let hash = Sha256::digest(body.as_bytes());
+    let etag = format!(
+        "\"{}\"",
+        hash[..8]
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>()
+    );
+
+    let mut headers = HeaderMap::new();
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        headers.insert(ETAG, value);
+    }
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    if is_fresh(request, &etag) {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(asset.content_type));
+    if asset.content_type.starts_with("text/html") {
+        headers.insert(
+            CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static(CONTENT_SECURITY_POLICY_VALUE),
+        );
+    }
+    (headers, body).into_response()
+}
+
+/// `GET /static/{file}`, the scripts and styles the pages use.
+pub async fn static_asset(Path(file): Path<String>, request: HeaderMap) -> Response {
+    serve(&file, &request).await
+}
+
+pub async fn index(request: HeaderMap) -> Response {
+    serve("index.html", &request).await
+}
+
+/// `GET /dashboard`, every public lobby at a glance.
+pub async fn dashboard(request: HeaderMap) -> Response {
+    serve("dashboard.html", &request).await
+}
+
+pub async fn serve_schema(request: HeaderMap) -> Response {
+    serve("schema.json", &request).await
 }
@@ -1,43 +1,268 @@
 use std::{
-    collections::{HashMap, VecDeque},
+    cmp::Reverse,
+    collections::{HashMap, HashSet, VecDeque},
+    iter,
     net::SocketAddr,
     time::Duration,
 };
 
 use anyhow::Context;
+use rand::seq::SliceRandom;
 use snakes_shared::{
-    ClientMessage, Direction, Map, MapPiece, PlayerData, ServerMessage, WatchUpdate,
+    AdminCommand, Annotation, Appearance, ClientMessage, Direction, InspectEvent, Map, MapPiece,
+    PlayerData, ServerMessage, Standing, Thumbnail, TurnDirection, WatchMessage, WatchUpdate,
+    WatcherMessage,
 };
 use tokio::{
-    sync::{mpsc, oneshot},
-    time::{Instant, Interval, interval, interval_at},
+    sync::{broadcast, mpsc, watch},
+    time::{Instant, Interval, interval, interval_at, sleep_until},
 };
 
-use crate::{ClientUpdate, tick_buffer::TickBuffer};
+use crate::{
+    ClientUpdate, HANDED_OUT_LOBBIES, JoinError, Player,
+    config::CONFIG,
+    input_queue::InputQueue,
+    lag::LagPolicy,
+    lobbies::Access,
+    metrics::{LobbyGauges, METRICS, Metrics},
+    rating::{self, INITIAL_RATING},
+    replays::{self, MAX_REPLAY_BYTES},
+    snapshot::{self, GameSnapshot, SnakeSnapshot},
+    stats::STATS,
+    tick_buffer::TickBuffer,
+};
+
+const TICK_BUFFER_SIZE: usize = 50;
+/// Capacity of the channels feeding a lobby, senders wait when they are full.
+const LOBBY_CHANNEL_SIZE: usize = 256;
+/// Longer names are cut off.
+const MAX_NAME_LEN: usize = 32;
+/// How many annotations a bot may publish per tick.
+const MAX_ANNOTATIONS: usize = 16;
+const MAX_LABEL_LEN: usize = 64;
+const MAX_CHAT_LEN: usize = 200;
+/// Watchers may send this many chat messages per [`CHAT_WINDOW`].
+pub const CHAT_BURST: usize = 5;
+pub const CHAT_WINDOW: Duration = Duration::from_secs(10);
+/// Lobbies don't speed up past this by themselves, and hosts can't make them either.
+pub const FASTEST_TICK: Duration = Duration::from_millis(200);
+/// How long an empty lobby stays open.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a handed out lobby waits for its players to show up, a restored lobby for its
+/// bots to come back, and a lobby with a roster for everyone on it before starting anyway.
+const FIRST_JOIN_WAIT: Duration = Duration::from_secs(60);
 
 struct ClientInfo {
     name: String,
-    msg: mpsc::UnboundedSender<ServerMessage>,
-    msg_count: usize,
+    msg: mpsc::Sender<ServerMessage>,
+    input: InputQueue,
     position: (usize, usize),
     tail: VecDeque<(usize, usize)>,
     tail_len: usize,
     death: usize,
     direction: Direction,
     id: usize,
+    /// The tick the snake last (re)spawned at.
+    spawned_at: usize,
     tick_diff: Option<usize>,
+    /// Set when a tick is sent, cleared when it is answered.
+    awaiting: bool,
+    latency: Option<Duration>,
+    registered: bool,
+    /// Only registered players are rated.
+    rating: Option<f64>,
+    /// `Debug` messages received this tick, only the first is passed on to watchers.
+    debug_received: usize,
+    appearance: Appearance,
+}
+
+/// How big a thumbnail may get per side.
+const THUMBNAIL_SIZE: usize = 32;
+
+/// What a lobby shares about itself on `GET /lobbies`, updated once per tick.
+#[derive(Debug, Clone, Default)]
+pub struct LobbyFeed {
+    pub tick: usize,
+    pub tick_ms: u64,
+    pub paused: bool,
+    pub map_size: (usize, usize),
+    pub thumbnail: Thumbnail,
+}
+
+/// Scales the map down to at most [`THUMBNAIL_SIZE`] cells per side, every cell showing
+/// what stands out most in the block it covers: a head, then a body, then an apple.
+fn thumbnail(map: &Map, map_size: (usize, usize)) -> Thumbnail {
+    fn rank(piece: MapPiece) -> u8 {
+        match piece {
+            MapPiece::SnakeHead(_) => 3,
+            MapPiece::Snake(_) => 2,
+            MapPiece::Apple => 1,
+            MapPiece::Empty => 0,
+        }
+    }
+
+    let block = map_size.0.max(map_size.1).div_ceil(THUMBNAIL_SIZE).max(1);
+    let size = (map_size.0.div_ceil(block), map_size.1.div_ceil(block));
+    let mut cells = vec![MapPiece::Empty; size.0 * size.1];
+    for (index, &piece) in map.iter().enumerate() {
+        let (x, y) = (index % map_size.0 / block, index / map_size.0 / block);
+        if let Some(cell) = cells.get_mut(x + y * size.0)
+            && rank(piece) > rank(*cell)
+        {
+            *cell = piece;
+        }
+    }
+    Thumbnail { size, map: cells }
+}
+
+/// A watcher who has chatted or picked a name.
+struct Chatter {
+    name: String,
+    /// When the messages still counting against the rate limit were sent.
+    sent: VecDeque<Instant>,
+    /// The name this watcher was muted under, it stays muted when picking another one.
+    muted_as: Option<String>,
+}
+
+impl Default for Chatter {
+    fn default() -> Self {
+        Self {
+            name: "spectator".to_string(),
+            sent: VecDeque::new(),
+            muted_as: None,
+        }
+    }
+}
+
+/// Strips control characters, cuts the message short and stars out blocked words.
+fn clean_chat(text: &str) -> String {
+    let text = text
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_CHAT_LEN)
+        .collect::<String>();
+    text.trim()
+        .split(' ')
+        .map(|word| {
+            let bare = word
+                .trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase();
+            if CONFIG.chat_blocklist.contains(&bare) {
+                "*".repeat(word.chars().count())
+            } else {
+                word.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Tells the watchers inspecting `snake` about what `event` makes.
+fn inspect(
+    watchers: &HashMap<SocketAddr, broadcast::Sender<WatchMessage>>,
+    inspecting: &HashMap<SocketAddr, usize>,
+    snake: usize,
+    event: impl FnOnce() -> InspectEvent,
+) {
+    let mut targets = inspecting
+        .iter()
+        .filter(|&(_, &s)| s == snake)
+        .filter_map(|(addr, _)| watchers.get(addr))
+        .peekable();
+    if targets.peek().is_none() {
+        return;
+    }
+    let msg = WatchMessage::Inspect(event());
+    for send in targets {
+        _ = send.send(msg.clone());
+    }
+}
+
+fn tick_of(msg: &ClientMessage) -> Option<u64> {
+    match msg {
+        ClientMessage::Turn(tick_id, _) | ClientMessage::NoTurn(tick_id) => Some(*tick_id),
+        ClientMessage::SetName(_)
+        | ClientMessage::Authenticate(_)
+        | ClientMessage::Appearance(_)
+        | ClientMessage::Debug { .. } => None,
+    }
+}
+
+fn turn_of(msg: &ClientMessage) -> Option<TurnDirection> {
+    match msg {
+        ClientMessage::Turn(_, turn) => Some(*turn),
+        _ => None,
+    }
+}
+
+/// Rules a lobby is created with.
+#[derive(Debug, Clone)]
+pub struct LobbySettings {
+    /// Length of a match in ticks, the lobby plays one endless match when unset.
+    pub match_ticks: Option<usize>,
+    pub max_players: usize,
+    /// Only these players may join, and the game waits for all of them before starting.
+    pub roster: Option<Vec<String>>,
+    /// Close the lobby once the first match is over.
+    pub single_match: bool,
+    /// Where the standings go when a match ends.
+    pub results: Option<mpsc::Sender<Vec<Standing>>>,
+    pub access: Access,
+    /// Start paused, the game only moves when stepped or resumed.
+    pub paused: bool,
+    /// Tick at this fixed rate instead of speeding up over time.
+    pub tick_ms: Option<u64>,
+}
+impl Default for LobbySettings {
+    fn default() -> Self {
+        Self {
+            match_ticks: CONFIG.match_ticks,
+            max_players: CONFIG.max_players,
+            roster: None,
+            single_match: false,
+            results: None,
+            access: Access::default(),
+            paused: false,
+            tick_ms: None,
+        }
+    }
 }
 
 pub struct Game {
-    compensate: bool,
+    settings: LobbySettings,
+    /// The tick the current match started at.
+    match_started: usize,
+    created: Instant,
+    lag_policy: LagPolicy,
+    /// When the lobby is holding back a tick for slow clients, this is the latest it will wait.
+    stalled: Option<Instant>,
+    /// Set once the server is shutting down, the lobby closes at this point.
+    closing_at: Option<Instant>,
     id: usize,
-    tb: TickBuffer<50>,
+    tb: TickBuffer<TICK_BUFFER_SIZE>,
+    tick_times: VecDeque<(u64, Instant)>,
     id_counter: usize,
-    new_clients: mpsc::UnboundedReceiver<ClientUpdate>,
-    msgs: mpsc::UnboundedReceiver<(SocketAddr, ClientMessage)>,
+    new_clients: mpsc::Receiver<ClientUpdate>,
+    msgs: mpsc::Receiver<(SocketAddr, ClientMessage)>,
     clients: HashMap<SocketAddr, ClientInfo>,
     interval: Interval,
-    watchers: HashMap<SocketAddr, mpsc::UnboundedSender<WatchUpdate>>,
+    watchers: HashMap<SocketAddr, broadcast::Sender<WatchMessage>>,
+    /// The snake every inspecting watcher follows.
+    inspecting: HashMap<SocketAddr, usize>,
+    chatters: HashMap<SocketAddr, Chatter>,
+    /// Chat names muted by an admin or the host.
+    muted: HashSet<String>,
+    /// Paused by an admin, no ticks are played until resumed.
+    paused: bool,
+    /// The tick rate was set by an admin and doesn't speed up anymore.
+    fixed_rate: bool,
+    /// Restored snakes waiting for a client with the same name to take them over.
+    dormant: HashMap<String, SnakeSnapshot>,
+    summary: watch::Sender<LobbyFeed>,
+    /// Every update of the current match, when playing timed matches.
+    recording: Vec<WatchUpdate>,
+    /// How big `recording` is as JSON.
+    recording_bytes: usize,
 
     map: Map,
     map_size: (usize, usize),
@@ -47,42 +272,151 @@ pub struct Game {
 impl Game {
     pub fn new(
         id: usize,
+        settings: LobbySettings,
     ) -> (
         Self,
-        mpsc::UnboundedSender<(SocketAddr, ClientMessage)>,
-        mpsc::UnboundedSender<ClientUpdate>,
+        mpsc::Sender<(SocketAddr, ClientMessage)>,
+        mpsc::Sender<ClientUpdate>,
     ) {
         info!("lobby {id}: started game");
+        let period = settings
+            .tick_ms
+            .map_or(Duration::from_secs(1), Duration::from_millis);
+        let (paused, fixed_rate) = (settings.paused, settings.tick_ms.is_some());
         let map_size = (20, 14);
-        let (msgs_send, msgs) = mpsc::unbounded_channel();
-        let (new_clients_send, new_clients) = mpsc::unbounded_channel();
+        let (msgs_send, msgs) = mpsc::channel(LOBBY_CHANNEL_SIZE);
+        let (new_clients_send, new_clients) = mpsc::channel(LOBBY_CHANNEL_SIZE);
         let apples = vec![(
             rand::random_range(0..map_size.0),
             rand::random_range(0..map_size.1),
         )];
-        (
-            Self {
-                compensate: false,
-                tb: TickBuffer::new(),
-                id,
-                id_counter: 0,
-                new_clients,
-                msgs,
-                clients: HashMap::new(),
-                interval: interval(Duration::from_secs(1)),
-                map: vec![MapPiece::Empty; map_size.0 * map_size.1],
-                map_size,
-                tick: 0,
-                apples,
-                watchers: HashMap::new(),
-            },
-            msgs_send,
-            new_clients_send,
-        )
+        let game = Self {
+            settings,
+            match_started: 0,
+            created: Instant::now(),
+            lag_policy: CONFIG.lag_policy,
+            stalled: None,
+            closing_at: None,
+            tb: TickBuffer::new(),
+            tick_times: VecDeque::new(),
+            id,
+            id_counter: 0,
+            new_clients,
+            msgs,
+            clients: HashMap::new(),
+            interval: interval(period),
+            map: vec![MapPiece::Empty; map_size.0 * map_size.1],
+            map_size,
+            tick: 0,
+            apples,
+            watchers: HashMap::new(),
+            inspecting: HashMap::new(),
+            chatters: HashMap::new(),
+            muted: HashSet::new(),
+            paused,
+            fixed_rate,
+            dormant: HashMap::new(),
+            summary: watch::Sender::new(LobbyFeed::default()),
+            recording: Vec::new(),
+            recording_bytes: 0,
+        };
+        game.publish_summary();
+        (game, msgs_send, new_clients_send)
+    }
+
+    pub fn restore(&mut self, snapshot: GameSnapshot) {
+        info!(
+            "lobby {}: restored {} snakes from tick {}",
+            self.id,
+            snapshot.snakes.len(),
+            snapshot.tick
+        );
+        let period = Duration::from_millis(snapshot.interval_ms);
+        self.interval = interval_at(Instant::now() + period, period);
+        self.map_size = snapshot.map_size;
+        self.map = vec![MapPiece::Empty; self.map_size.0 * self.map_size.1];
+        self.tick = snapshot.tick;
+        self.match_started = snapshot.tick;
+        self.apples = snapshot.apples;
+        self.id_counter = snapshot.id_counter;
+        self.dormant = snapshot
+            .snakes
+            .into_iter()
+            .map(|s| (s.name.clone(), s))
+            .collect();
+        self.publish_summary();
+    }
+
+    /// Follows what the dashboard shows about this lobby.
+    pub fn summary(&self) -> watch::Receiver<LobbyFeed> {
+        self.summary.subscribe()
+    }
+
+    fn publish_summary(&self) {
+        self.summary.send_replace(LobbyFeed {
+            tick: self.tick,
+            tick_ms: self.interval.period().as_millis() as u64,
+            paused: self.paused,
+            map_size: self.map_size,
+            thumbnail: thumbnail(&self.map, self.map_size),
+        });
+    }
+
+    fn snapshot(&self) -> GameSnapshot {
+        let active = self.clients.values().map(|c| SnakeSnapshot {
+            id: c.id,
+            name: c.name.clone(),
+            position: c.position,
+            direction: c.direction,
+            tail: c.tail.clone(),
+            tail_len: c.tail_len,
+            death: c.death,
+        });
+        GameSnapshot {
+            id: self.id,
+            map_size: self.map_size,
+            tick: self.tick,
+            interval_ms: self.interval.period().as_millis() as u64,
+            apples: self.apples.clone(),
+            id_counter: self.id_counter,
+            snakes: active.chain(self.dormant.values().cloned()).collect(),
+        }
+    }
+
+    /// Saves the lobby to the snapshot directory, if there is one.
+    /// Single match lobbies aren't worth bringing back and are never saved,
+    /// neither are private ones as anyone could restore them under their id, nor
+    /// handed out ones as their ids are never started again.
+    pub async fn save(&self) -> anyhow::Result<()> {
+        let Some(dir) = &CONFIG.snapshot_dir else {
+            return Ok(());
+        };
+        if self.settings.single_match
+            || !self.settings.access.is_public()
+            || self.id >= HANDED_OUT_LOBBIES
+        {
+            return Ok(());
+        }
+        snapshot::save(dir, &self.snapshot()).await
+    }
+
+    /// Every cell without a snake or an apple on it, in random order.
+    fn free_cells(&self) -> Vec<(usize, usize)> {
+        let taken = self
+            .clients
+            .values()
+            .flat_map(|c| iter::once(c.position).chain(c.tail.iter().copied()))
+            .chain(self.apples.iter().copied())
+            .collect::<HashSet<_>>();
+        let mut free = (0..self.map_size.1)
+            .flat_map(|y| (0..self.map_size.0).map(move |x| (x, y)))
+            .filter(|cell| !taken.contains(cell))
+            .collect::<Vec<_>>();
+        free.shuffle(&mut rand::rng());
+        free
     }
 
     fn spawn_apple(&mut self, count: usize) {
-        self.apples = Vec::new();
         for _ in 0..count {
             'outer: for _ in 0..100 {
                 let position = (
@@ -118,7 +452,7 @@ impl Game {
             let index = x + (y * self.map_size.0);
             self.map[index] = MapPiece::Apple;
         }
-        let mut needs_new_apples = false;
+        let mut eaten = Vec::new();
         for c in self.clients.values_mut() {
             c.tail.push_front(c.position);
             if c.tail.len() > c.tail_len {
@@ -158,7 +492,11 @@ impl Game {
             let index = c.position.0 + (c.position.1 * self.map_size.0);
             if self.map[index] == MapPiece::Apple {
                 c.tail_len += 1;
-                needs_new_apples = true;
+                eaten.push(c.position);
+                STATS.update(&c.name, |s| {
+                    s.apples += 1;
+                    s.max_length = s.max_length.max(c.tail_len);
+                });
             }
             self.map[index] = MapPiece::SnakeHead(c.id);
             for tail in &c.tail {
@@ -168,11 +506,14 @@ impl Game {
                 }
             }
         }
-        if needs_new_apples {
+        // apples spawned by admins come on top of the one the game keeps around
+        self.apples.retain(|apple| !eaten.contains(apple));
+        if self.apples.is_empty() {
             self.spawn_apple(1);
         }
 
         let mut dead_snakes = Vec::new();
+        let mut killers = Vec::new();
         'outer: for (a1, c1) in &self.clients {
             for (a2, c2) in &self.clients {
                 if c1.position == c2.position && a1 != a2 {
@@ -183,27 +524,68 @@ impl Game {
                 for t in &c2.tail {
                     if *t == c1.position {
                         dead_snakes.push(*a1);
+                        if a1 != a2 {
+                            killers.push(*a2);
+                        }
                         continue 'outer;
                     }
                 }
             }
         }
+        // head on collisions are found from both snakes
+        dead_snakes.sort();
+        dead_snakes.dedup();
         let map_size = self.map_size;
+        let mut spawns = self.free_cells();
         for snake in dead_snakes {
             let Some(data) = self.clients.get_mut(&snake) else {
                 unreachable!()
             };
-            data.position = (
-                rand::random_range(0..map_size.0),
-                rand::random_range(0..map_size.1),
-            );
+            STATS.update(&data.name, |s| {
+                s.deaths += 1;
+                s.max_length = s.max_length.max(data.tail_len);
+                s.end_life(data.spawned_at, self.tick);
+            });
+            // on a packed map the snake may have to respawn on top of something
+            data.position = spawns.pop().unwrap_or_else(|| {
+                (
+                    rand::random_range(0..map_size.0),
+                    rand::random_range(0..map_size.1),
+                )
+            });
             data.tail_len = 2;
             data.tail.clear();
             data.death += 1;
+            data.spawned_at = self.tick;
+        }
+        for killer in killers {
+            if let Some(data) = self.clients.get(&killer) {
+                STATS.update(&data.name, |s| s.kills += 1);
+            }
+        }
+
+        let data = self.watch_update();
+        if self.settings.match_ticks.is_some() && self.recording_bytes < MAX_REPLAY_BYTES {
+            self.recording_bytes += serde_json::to_vec(&data).map_or(0, |json| json.len());
+            self.recording.push(data.clone());
+        }
+        self.broadcast(WatchMessage::Update(data));
+        if self
+            .settings
+            .match_ticks
+            .is_some_and(|n| self.tick - self.match_started >= n)
+        {
+            self.end_match();
+            if self.settings.single_match {
+                return Err(anyhow::Error::msg("match over"));
+            }
         }
+        Ok(())
+    }
 
-        let mut dead_clients = Vec::new();
-        let data = WatchUpdate {
+    /// The board as watchers see it.
+    fn watch_update(&self) -> WatchUpdate {
+        WatchUpdate {
             map: self.map.clone(),
             map_size: self.map_size,
             clients: self
@@ -212,174 +594,1024 @@ impl Game {
                 .map(|s| PlayerData {
                     name: s.name.clone(),
                     position: s.position,
+                    tail: s.tail.iter().copied().collect(),
                     tail_len: s.tail_len,
                     death: s.death,
                     id: s.id,
+                    latency_ms: s.latency.map(|l| l.as_millis() as u64),
+                    rating: s.rating,
+                    appearance: s.appearance.clone(),
                 })
                 .collect(),
+        }
+    }
+
+    fn broadcast(&mut self, msg: WatchMessage) {
+        self.watchers
+            .retain(|_, send| send.send(msg.clone()).is_ok());
+    }
+
+    /// Whether the game may start, lobbies with a roster wait for everyone on it for a while.
+    fn ready(&self) -> bool {
+        let Some(roster) = &self.settings.roster else {
+            return true;
         };
-        for (client, send) in &self.watchers {
-            if send.send(data.clone()).is_err() {
-                dead_clients.push(*client);
+        self.tick > 0
+            || self.created.elapsed() >= FIRST_JOIN_WAIT
+            || roster
+                .iter()
+                .all(|name| self.clients.values().any(|c| &c.name == name))
+    }
+
+    /// Publishes the standings, rates the registered players against each other
+    /// and puts everyone back at the start.
+    fn end_match(&mut self) {
+        let mut ranked = self.clients.values_mut().collect::<Vec<_>>();
+        ranked.sort_by_key(|c| (Reverse(c.tail_len), c.death));
+        let mut standings = Vec::<Standing>::new();
+        for (i, c) in ranked.iter().enumerate() {
+            let place = match standings.last() {
+                Some(prev) if prev.tail_len == c.tail_len && prev.death == c.death => prev.place,
+                _ => i + 1,
+            };
+            standings.push(Standing {
+                place,
+                name: c.name.clone(),
+                tail_len: c.tail_len,
+                death: c.death,
+                rating: None,
+            });
+            STATS.update(&c.name, |s| s.matches += 1);
+        }
+
+        let rated = (0..ranked.len())
+            .filter(|i| ranked[*i].registered)
+            .collect::<Vec<_>>();
+        if rated.len() > 1 {
+            // the same bot may be playing in other lobbies, so go by its current rating
+            // and only apply the change this match made to it
+            let before = rated
+                .iter()
+                .map(|&i| (STATS.rating(&ranked[i].name), standings[i].place))
+                .collect::<Vec<_>>();
+            let after = rating::update(&before);
+            for ((&i, (old, _)), new) in rated.iter().zip(before).zip(after) {
+                let mut rating = new;
+                STATS.update(&ranked[i].name, |s| {
+                    rating = s.rating.unwrap_or(INITIAL_RATING) + new - old;
+                    s.rating = Some(rating);
+                    s.rated_matches += 1;
+                });
+                ranked[i].rating = Some(rating);
+                standings[i].rating = Some(rating);
             }
         }
-        for client in dead_clients {
-            self.watchers.remove(&client);
+        if let Some(winner) = standings.first() {
+            info!("lobby {}: match over, {} won", self.id, winner.name);
+        }
+        if let Some(results) = &self.settings.results {
+            _ = results.try_send(standings.clone());
+        }
+        let frames = std::mem::take(&mut self.recording);
+        let bytes = std::mem::take(&mut self.recording_bytes);
+        if self.settings.access.is_public() && !frames.is_empty() {
+            let replay = replays::store(self.id, standings.clone(), frames, bytes);
+            debug!("lobby {}: saved the match as replay {replay}", self.id);
+        }
+
+        for c in ranked {
+            _ = c.msg.try_send(ServerMessage::MatchEnded {
+                standings: standings.clone(),
+            });
+        }
+        self.reset_snakes();
+        self.match_started = self.tick;
+    }
+
+    /// Puts every snake back at the start with a clean score.
+    fn reset_snakes(&mut self) {
+        let mut spawns = self.free_cells();
+        for c in self.clients.values_mut() {
+            STATS.update(&c.name, |s| {
+                s.max_length = s.max_length.max(c.tail_len);
+                s.end_life(c.spawned_at, self.tick);
+            });
+            c.position = spawns.pop().unwrap_or(c.position);
+            c.tail_len = 2;
+            c.tail.clear();
+            c.death = 0;
+            c.spawned_at = self.tick;
+        }
+    }
+
+    fn admin(&mut self, command: AdminCommand) -> Result<(), String> {
+        match command {
+            AdminCommand::Pause => self.paused = true,
+            AdminCommand::Resume => self.paused = false,
+            AdminCommand::Step => return Err("only a paused lobby can be stepped".to_string()),
+            AdminCommand::Kick { id, reason } => {
+                let Some(addr) = self
+                    .clients
+                    .iter()
+                    .find(|(_, c)| c.id == id)
+                    .map(|(addr, _)| *addr)
+                else {
+                    return Err(format!("there is no snake {id}"));
+                };
+                self.kick(
+                    addr,
+                    reason.unwrap_or_else(|| "kicked by an admin".to_string()),
+                );
+            }
+            AdminCommand::ResetScores => self.reset_snakes(),
+            AdminCommand::SetTickRate { ms } => {
+                if ms == 0 {
+                    return Err("the tick rate must be above 0ms".to_string());
+                }
+                let period = Duration::from_millis(ms);
+                self.interval = interval_at(Instant::now() + period, period);
+                self.fixed_rate = true;
+            }
+            AdminCommand::SpawnApple { x, y } => {
+                if x >= self.map_size.0 || y >= self.map_size.1 {
+                    return Err(format!("({x}, {y}) is outside the map"));
+                }
+                if !self.apples.contains(&(x, y)) {
+                    self.apples.push((x, y));
+                }
+            }
+            AdminCommand::Announce(text) => {
+                for cli in self.clients.values() {
+                    _ = cli
+                        .msg
+                        .try_send(ServerMessage::Announcement { text: text.clone() });
+                }
+                self.broadcast(WatchMessage::Announcement { text });
+            }
+            AdminCommand::Mute { name } => {
+                let name = sanitize_name(&name);
+                for chatter in self.chatters.values_mut() {
+                    if chatter.name == name {
+                        chatter.muted_as = Some(name.clone());
+                    }
+                }
+                self.muted.insert(name);
+            }
+            AdminCommand::Unmute { name } => {
+                let name = sanitize_name(&name);
+                if !self.muted.remove(&name) {
+                    return Err(format!("{name} is not muted"));
+                }
+                for chatter in self.chatters.values_mut() {
+                    if chatter.muted_as.as_ref() == Some(&name) {
+                        chatter.muted_as = None;
+                    }
+                }
+            }
         }
         Ok(())
     }
     async fn handle_message(&mut self, who: SocketAddr, msg: ClientMessage) -> anyhow::Result<()> {
+        if let ClientMessage::Debug {
+            tick_id,
+            annotations,
+        } = msg
+        {
+            self.forward_debug(who, tick_id, annotations);
+            return Ok(());
+        }
         let Some(cli) = self.clients.get_mut(&who) else {
-            error!(
+            debug!(
                 "lobby {}: got message for non-existent client: {who}",
                 self.id
             );
             return Ok(());
         };
         let tick_id = match msg {
-            ClientMessage::Turn(tick_id, turn_direction) => {
-                cli.direction += turn_direction;
-                Some(tick_id)
-            }
-            ClientMessage::NoTurn(tick_id) => Some(tick_id),
-            ClientMessage::SetName(_) => None,
+            ClientMessage::Turn(tick_id, _) | ClientMessage::NoTurn(tick_id) => tick_id,
+            ClientMessage::SetName(_)
+            | ClientMessage::Authenticate(_)
+            | ClientMessage::Appearance(_)
+            | ClientMessage::Debug { .. } => return Ok(()),
         };
-        if let Some(tick_id) = tick_id {
-            cli.tick_diff = self.tb.since(tick_id);
+        cli.tick_diff = self.tb.since(tick_id);
+        let after = self
+            .tick_times
+            .iter()
+            .find(|(id, _)| *id == tick_id)
+            .map(|(_, sent)| sent.elapsed());
+        if after.is_some() {
+            cli.latency = after;
+        }
+        inspect(&self.watchers, &self.inspecting, cli.id, || {
+            InspectEvent::Answered {
+                snake: cli.id,
+                tick_id,
+                turn: turn_of(&msg),
+                after_ms: after.map(|a| a.as_millis() as u64),
+            }
+        });
+        if self
+            .tick_times
+            .front()
+            .is_some_and(|(id, _)| *id == tick_id)
+        {
+            cli.awaiting = false;
+        }
+        if cli.input.push(msg) + cli.debug_received > CONFIG.flood_limit {
+            self.kick_flooder(who);
         }
         Ok(())
     }
 
+    fn kick_flooder(&mut self, who: SocketAddr) {
+        let reason = format!(
+            "sent more than {} messages in a single tick",
+            CONFIG.flood_limit
+        );
+        self.kick(who, reason);
+    }
+
+    /// Passes a bot's annotations on to the watchers, cut down to size and only once
+    /// per tick so a bot can't flood them. They count towards the flood limit like any
+    /// other message.
+    fn forward_debug(&mut self, who: SocketAddr, tick_id: u64, mut annotations: Vec<Annotation>) {
+        let Some(cli) = self.clients.get_mut(&who) else {
+            return;
+        };
+        cli.debug_received += 1;
+        if cli.input.received() + cli.debug_received > CONFIG.flood_limit {
+            self.kick_flooder(who);
+            return;
+        }
+        if cli.debug_received > 1 || self.tb.since(tick_id).is_none() {
+            debug!(
+                "lobby {}: dropped annotations from {who} for tick {tick_id}",
+                self.id
+            );
+            return;
+        }
+        let cells = self.map_size.0 * self.map_size.1;
+        annotations.truncate(MAX_ANNOTATIONS);
+        for annotation in &mut annotations {
+            match annotation {
+                Annotation::Path(path) => path.truncate(cells),
+                Annotation::Label { text, .. } => {
+                    *text = text.chars().take(MAX_LABEL_LEN).collect();
+                }
+                Annotation::Target(_) => (),
+            }
+        }
+        let snake = cli.id;
+        self.broadcast(WatchMessage::Debug {
+            snake,
+            tick_id,
+            annotations,
+        });
+    }
+
+    fn watcher_message(&mut self, addr: SocketAddr, msg: WatcherMessage) {
+        if !self.watchers.contains_key(&addr) {
+            return;
+        }
+        match msg {
+            WatcherMessage::Inspect(Some(snake)) => {
+                self.inspecting.insert(addr, snake);
+            }
+            WatcherMessage::Inspect(None) => {
+                self.inspecting.remove(&addr);
+            }
+            WatcherMessage::SetName(name) => {
+                let name = sanitize_name(&name);
+                if self.muted.contains(&name) {
+                    if let Some(send) = self.watchers.get(&addr) {
+                        let reason = format!("{name} is muted");
+                        _ = send.send(WatchMessage::ChatRefused { reason });
+                    }
+                    return;
+                }
+                self.chatters.entry(addr).or_default().name = name;
+            }
+            WatcherMessage::Chat(text) => self.chat(addr, text),
+        }
+    }
+
+    /// Passes a chat message on to every watcher, unless its sender is muted or
+    /// has been chatting too much lately.
+    fn chat(&mut self, addr: SocketAddr, text: String) {
+        let text = clean_chat(&text);
+        if text.is_empty() {
+            return;
+        }
+        let chatter = self.chatters.entry(addr).or_default();
+        let now = Instant::now();
+        chatter.sent.retain(|&at| now - at < CHAT_WINDOW);
+        let refused = if chatter.muted_as.is_some() || self.muted.contains(&chatter.name) {
+            Some("you are muted".to_string())
+        } else if chatter.sent.len() >= CHAT_BURST {
+            Some(format!(
+                "slow down, at most {CHAT_BURST} messages per {}s",
+                CHAT_WINDOW.as_secs()
+            ))
+        } else {
+            None
+        };
+        if let Some(reason) = refused {
+            if let Some(send) = self.watchers.get(&addr) {
+                _ = send.send(WatchMessage::ChatRefused { reason });
+            }
+            return;
+        }
+        chatter.sent.push_back(now);
+        let name = chatter.name.clone();
+        info!("lobby {}: chat from {addr}/{name}: {text}", self.id);
+        self.broadcast(WatchMessage::Chat { name, text });
+    }
+
+    fn kick(&mut self, who: SocketAddr, reason: String) {
+        let Some(cli) = self.remove_client(who) else {
+            return;
+        };
+        warn!("lobby {}: kicked {who}/{}: {reason}", self.id, cli.name);
+        inspect(&self.watchers, &self.inspecting, cli.id, || {
+            InspectEvent::Kicked {
+                snake: cli.id,
+                reason: reason.clone(),
+            }
+        });
+        _ = cli.msg.try_send(ServerMessage::Kicked { reason });
+    }
+
+    fn remove_client(&mut self, who: SocketAddr) -> Option<ClientInfo> {
+        let cli = self.clients.remove(&who)?;
+        info!("lobby {}: {who} left", self.id);
+        Self::record_leave(&cli, self.tick);
+        Some(cli)
+    }
+
+    fn record_leave(cli: &ClientInfo, tick: usize) {
+        STATS.update(&cli.name, |s| {
+            s.sessions += 1;
+            s.max_length = s.max_length.max(cli.tail_len);
+            s.end_life(cli.spawned_at, tick);
+        });
+    }
+
+    /// Sends the next `Tick` to clients according to the lag policy and advances the game.
+    async fn advance(&mut self) -> anyhow::Result<()> {
+        if self.stalled.take().is_some() {
+            self.interval.reset();
+        }
+        let tick_id = self.tb.next();
+        self.speedup();
+        self.tick_times.push_front((tick_id, Instant::now()));
+        self.tick_times.truncate(TICK_BUFFER_SIZE);
+        let mut to_remove = Vec::new();
+        for (addr, cli) in &mut self.clients {
+            cli.debug_received = 0;
+            let received = cli.input.received();
+            let answer = cli.input.take();
+            if let Some(ClientMessage::Turn(_, turn_direction)) = answer {
+                cli.direction += turn_direction;
+            }
+            inspect(&self.watchers, &self.inspecting, cli.id, || {
+                InspectEvent::Applied {
+                    snake: cli.id,
+                    answered: answer.as_ref().and_then(tick_of),
+                    turn: answer.as_ref().and_then(turn_of),
+                    dropped: received.saturating_sub(usize::from(answer.is_some())),
+                }
+            });
+            if self.lag_policy == LagPolicy::SkipSlow {
+                let skip = if let Some(d) = cli.tick_diff
+                    && d > 1
+                {
+                    cli.tick_diff = Some(d - 1);
+                    true
+                } else if cli.tick_diff.is_none() {
+                    cli.tick_diff = Some(1);
+                    true
+                } else {
+                    false
+                };
+                if skip {
+                    inspect(&self.watchers, &self.inspecting, cli.id, || {
+                        InspectEvent::Skipped {
+                            snake: cli.id,
+                            tick_id,
+                        }
+                    });
+                    continue;
+                }
+            }
+            inspect(&self.watchers, &self.inspecting, cli.id, || {
+                InspectEvent::Sent {
+                    snake: cli.id,
+                    tick_id,
+                    your_position: cli.position,
+                    your_direction: cli.direction,
+                }
+            });
+            let e = cli.msg.try_send(ServerMessage::Tick {
+                tick_id,
+                map: self.map.clone(),
+                map_size: self.map_size,
+                your_direction: cli.direction,
+                your_position: cli.position,
+            });
+            match e {
+                Ok(()) => (),
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    warn!("lobby {}: {addr}/{} is not keeping up", self.id, cli.name);
+                    Metrics::add(&METRICS.players_dropped_slow, 1);
+                    to_remove.push(*addr)
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => to_remove.push(*addr),
+            }
+            cli.awaiting = true;
+        }
+        for cli in to_remove {
+            self.remove_client(cli);
+        }
+        self.handle_tick().await
+    }
+
     fn speedup(&mut self) {
+        if self.fixed_rate {
+            return;
+        }
         let dur = self.interval.period();
         let m = Duration::from_secs_f32(0.01);
-        if dur > m && dur > Duration::from_secs_f32(0.2) {
+        if dur > m && dur > FASTEST_TICK {
             let new = dur - m;
             self.interval = interval_at(Instant::now() + new, new);
         }
     }
 
     pub async fn tick(&mut self) -> anyhow::Result<()> {
-        let (empty_send, empty_recv) =
-            oneshot::channel::<Result<ClientUpdate, (SocketAddr, ClientMessage)>>();
-        if self.clients.is_empty() && self.watchers.is_empty() {
+        let res = self.step().await;
+        let outdated = {
+            let feed = self.summary.borrow();
+            feed.tick != self.tick
+                || feed.paused != self.paused
+                || feed.tick_ms != self.interval.period().as_millis() as u64
+        };
+        if outdated {
+            self.publish_summary();
+        }
+        METRICS.set_lobby(
+            self.id,
+            LobbyGauges {
+                players: self.clients.len(),
+                watchers: self.watchers.len(),
+            },
+        );
+        res
+    }
+
+    async fn step(&mut self) -> anyhow::Result<()> {
+        if self.closing_at.is_some() && self.clients.is_empty() {
+            return Err(anyhow::Error::msg("server shutting down"));
+        }
+        let msg = if self.clients.is_empty() && self.watchers.is_empty() {
+            let idle =
+                if (self.tick == 0 && self.id >= HANDED_OUT_LOBBIES) || !self.dormant.is_empty() {
+                    FIRST_JOIN_WAIT
+                } else {
+                    IDLE_TIMEOUT
+                };
             tokio::select! {
-                _ = tokio::time::sleep(Duration::from_secs(5)) => {
+                _ = tokio::time::sleep(idle) => {
                     return Err(anyhow::Error::msg("no clients"));
                 }
                 msg = self.new_clients.recv() => {
-                    let msg = msg.context("new client pipe is dead")?;
-                    _ = empty_send.send(Ok(msg));
+                    Ok(msg.context("new client pipe is dead")?)
                 }
                 msg = self.msgs.recv() => {
-                    let msg = msg.context("msg pipe is dead")?;
-                    _ = empty_send.send(Err(msg));
+                    Err(msg.context("msg pipe is dead")?)
                 }
             }
-        }
-        let msg = tokio::select! {
-            msg = empty_recv => msg?,
-            _ = self.interval.tick() => {
-                let tick_id = self.tb.next();
-                self.speedup();
-                let mut to_remove = Vec::new();
-                for (addr, cli) in &mut self.clients {
-                    // println!("{}: {:?}", cli.name, cli.tick_diff);
-                    // if let Some(d) =  cli.tick_diff
-                    //     && (d > 20 || cli.tick_diff.is_none()) {
-                    //     self.compensate = true;
-                    //     println!("compensate")
-                    // }
-                    if let Some(d) = cli.tick_diff && d > 1 {
-                        cli.tick_diff = Some(d - 1);
-                        continue;
-                    } else if cli.tick_diff.is_none() {
-                        cli.tick_diff = Some(1);
-                        continue;
+        } else {
+            tokio::select! {
+                _ = self.interval.tick(), if self.stalled.is_none() => {
+                    if self.paused || !self.ready() {
+                        return Ok(());
                     }
-                    let e = cli.msg.send(ServerMessage::Tick {
-                        tick_id,
-                        map: self.map.clone(),
-                        map_size: self.map_size,
-                        your_direction: cli.direction,
-                        your_position: cli.position,
-                    });
-                    if e.is_err() {
-                        to_remove.push(*addr)
+                    if let LagPolicy::Lockstep { timeout } = self.lag_policy
+                        && self.clients.values().any(|c| c.awaiting)
+                    {
+                        self.stalled = Some(Instant::now() + timeout);
+                        return Ok(());
                     }
-                    cli.msg_count = 0;
+                    return self.advance().await;
                 }
-                for cli in to_remove {
-                    info!("lobby {}: {cli} left",self.id);
-                    self.clients.remove(&cli);
+                _ = sleep_until(self.stalled.unwrap_or_else(Instant::now)), if self.stalled.is_some() => {
+                    return self.advance().await;
+                }
+                _ = sleep_until(self.closing_at.unwrap_or_else(Instant::now)), if self.closing_at.is_some() => {
+                    return Err(anyhow::Error::msg("server shutting down"));
+                }
+                msg = self.new_clients.recv() => {
+                    let msg = msg.context("new client pipe is dead")?;
+                    Ok(msg)
+                }
+                msg = self.msgs.recv() => {
+                    let (addr, msg) = msg.context("msg pipe is dead")?;
+                    Err((addr,msg))
                 }
-                return self.handle_tick().await;
-            }
-            msg = self.new_clients.recv() => {
-                let msg = msg.context("new client pipe is dead")?;
-                Ok(msg)
-            }
-            msg = self.msgs.recv() => {
-                let (addr, msg) = msg.context("msg pipe is dead")?;
-                Err((addr,msg))
             }
         };
         match msg {
             Ok(msg) => match msg {
-                ClientUpdate::Join(addr, name, pipe) => {
-                    let (msg_send, msg_recv) = mpsc::unbounded_channel();
+                ClientUpdate::Join(addr, _, pipe) if self.closing_at.is_some() => {
+                    info!("lobby {}: turned away {addr}, shutting down", self.id);
+                    let reason = "the server is shutting down".to_string();
+                    _ = pipe.send(Err(JoinError::Rejected(reason)));
+                }
+                ClientUpdate::Join(addr, player, pipe)
+                    if self
+                        .settings
+                        .roster
+                        .as_ref()
+                        .is_some_and(|roster| !roster.contains(&player.name)) =>
+                {
+                    info!("lobby {}: turned away {addr}, not on the roster", self.id);
+                    let reason = format!("{} is not playing in this lobby", player.name);
+                    _ = pipe.send(Err(JoinError::Rejected(reason)));
+                }
+                ClientUpdate::Join(addr, _, pipe)
+                    if self.clients.len() >= self.settings.max_players =>
+                {
+                    info!("lobby {}: turned away {addr}, the lobby is full", self.id);
+                    _ = pipe.send(Err(JoinError::Full {
+                        max_players: self.settings.max_players,
+                    }));
+                }
+                ClientUpdate::Join(
+                    addr,
+                    Player {
+                        name,
+                        registered,
+                        appearance,
+                    },
+                    pipe,
+                ) => {
+                    let name = sanitize_name(&name);
+                    let appearance = clean_appearance(appearance);
+                    let rating = registered.then(|| STATS.rating(&name));
+                    let (msg_send, msg_recv) = mpsc::channel(CONFIG.player_buffer);
                     trace!("lobby {}: got new client: {addr} | {name}", self.id);
-                    _ = pipe.send(msg_recv);
-                    let position = 'outer: loop {
-                        let x = rand::random_range(0..self.map_size.0);
-                        let y = rand::random_range(0..self.map_size.1);
-                        for c in self.clients.values() {
-                            if c.position == (x, y) {
-                                continue 'outer;
-                            }
-                        }
-                        break (x, y);
+                    if let Some(snake) = self.dormant.remove(&name) {
+                        info!("lobby {}: {addr} took over {name}", self.id);
+                        _ = pipe.send(Ok(msg_recv));
+                        self.clients.insert(
+                            addr,
+                            ClientInfo {
+                                id: snake.id,
+                                name,
+                                msg: msg_send,
+                                input: InputQueue::default(),
+                                position: snake.position,
+                                direction: snake.direction,
+                                tail: snake.tail,
+                                tail_len: snake.tail_len,
+                                death: snake.death,
+                                spawned_at: self.tick,
+                                tick_diff: Some(0),
+                                awaiting: false,
+                                latency: None,
+                                registered,
+                                rating,
+                                debug_received: 0,
+                                appearance,
+                            },
+                        );
+                        return Ok(());
+                    }
+                    let Some(position) = self.free_cells().pop() else {
+                        info!("lobby {}: turned away {addr}, no room on the map", self.id);
+                        let reason = "there is no room left on the map".to_string();
+                        _ = pipe.send(Err(JoinError::Rejected(reason)));
+                        return Ok(());
                     };
+                    _ = pipe.send(Ok(msg_recv));
                     self.clients.insert(
                         addr,
                         ClientInfo {
                             id: self.id_counter,
                             name,
                             msg: msg_send,
-                            msg_count: 0,
+                            input: InputQueue::default(),
                             position,
                             direction: Direction::from(rand::random_range(0..4)),
                             tail: VecDeque::new(),
                             tail_len: 2,
                             death: 0,
+                            spawned_at: self.tick,
                             tick_diff: Some(0),
+                            awaiting: false,
+                            latency: None,
+                            registered,
+                            rating,
+                            debug_received: 0,
+                            appearance,
                         },
                     );
                     self.id_counter += 1;
                 }
+                ClientUpdate::Shutdown(grace) => {
+                    self.closing_at = Some(Instant::now() + grace);
+                    for cli in self.clients.values() {
+                        _ = cli.msg.try_send(ServerMessage::ServerShuttingDown {
+                            seconds_left: grace.as_secs(),
+                        });
+                    }
+                }
+                ClientUpdate::Snapshot(reply) => {
+                    _ = reply.send(self.save().await);
+                }
+                ClientUpdate::Left(addr) => {
+                    self.remove_client(addr);
+                }
+                ClientUpdate::Admin(AdminCommand::Step, reply) if self.paused => {
+                    trace!("lobby {}: admin: Step", self.id);
+                    _ = reply.send(Ok(()));
+                    return self.advance().await;
+                }
+                ClientUpdate::Admin(command, reply) => {
+                    info!("lobby {}: admin: {command:?}", self.id);
+                    _ = reply.send(self.admin(command));
+                }
                 ClientUpdate::Watcher(addr, send) => {
                     info!("lobby {}: watcher joined at {addr}", self.id);
+                    // so new watchers, and paused lobbies, don't leave them with an empty board
+                    _ = send.send(WatchMessage::Update(self.watch_update()));
                     self.watchers.insert(addr, send);
                 }
+                ClientUpdate::FromWatcher(addr, msg) => self.watcher_message(addr, msg),
+                ClientUpdate::State(reply) => _ = reply.send(self.watch_update()),
+                ClientUpdate::WatcherLeft(addr) => {
+                    self.inspecting.remove(&addr);
+                    self.chatters.remove(&addr);
+                    if self.watchers.remove(&addr).is_some() {
+                        info!("lobby {}: watcher left at {addr}", self.id);
+                    }
+                }
             },
             Err((addr, msg)) => {
-                let cl = self
-                    .clients
-                    .get_mut(&addr)
-                    .context(format!("missing client: {addr}"))?;
-                cl.msg_count += 1;
-                if cl.msg_count == 2 || cl.msg_count.is_multiple_of(10) {
-                    warn!(
-                        "lobby {}: {addr}/{} sent too many messages: {}",
-                        self.id, cl.name, cl.msg_count
-                    );
-                }
-                if cl.msg_count != 1 {
-                    return Ok(());
-                }
                 self.handle_message(addr, msg).await?;
+                if self.stalled.is_some() && self.clients.values().all(|c| !c.awaiting) {
+                    return self.advance().await;
+                }
             }
         }
         Ok(())
     }
 }
+
+/// Unicode format characters (category Cf): invisible, but zero-width ones make a name
+/// look like another and bidi controls reorder the text around them.
+fn is_format(c: char) -> bool {
+    matches!(
+        c,
+        '\u{ad}'
+            | '\u{600}'..='\u{605}'
+            | '\u{61c}'
+            | '\u{6dd}'
+            | '\u{70f}'
+            | '\u{890}'..='\u{891}'
+            | '\u{8e2}'
+            | '\u{180e}'
+            | '\u{200b}'..='\u{200f}'
+            | '\u{202a}'..='\u{202e}'
+            | '\u{2060}'..='\u{2064}'
+            | '\u{2066}'..='\u{206f}'
+            | '\u{feff}'
+            | '\u{fff9}'..='\u{fffb}'
+            | '\u{110bd}'
+            | '\u{110cd}'
+            | '\u{13430}'..='\u{1343f}'
+            | '\u{1bca0}'..='\u{1bca3}'
+            | '\u{1d173}'..='\u{1d17a}'
+            | '\u{e0001}'
+            | '\u{e0020}'..='\u{e007f}'
+    )
+}
+
+/// Names end up on every watcher's screen, keep them to printable text without markup.
+pub fn sanitize_name(name: &str) -> String {
+    let name = name
+        .chars()
+        .filter(|&c| {
+            !c.is_control() && !is_format(c) && !matches!(c, '<' | '>' | '&' | '"' | '\'' | '`')
+        })
+        .take(MAX_NAME_LEN)
+        .collect::<String>();
+    match name.trim() {
+        "" => "snake".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// Colors are drawn straight onto the watchers' canvas, anything but `#rrggbb` is dropped
+/// in favour of the palette.
+fn clean_appearance(appearance: Appearance) -> Appearance {
+    let color = appearance.color.filter(|color| {
+        color.len() == 7
+            && color.starts_with('#')
+            && color[1..].chars().all(|c| c.is_ascii_hexdigit())
+    });
+    Appearance {
+        color: color.map(|color| color.to_lowercase()),
+        ..appearance
+    }
+}
+
+impl Drop for Game {
+    fn drop(&mut self) {
+        for cli in self.clients.values() {
+            Self::record_leave(cli, self.tick);
+        }
+        METRICS.remove_lobby(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{iter, net::SocketAddr, time::Duration};
+
+    use snakes_shared::{
+        AdminCommand, Appearance, ClientMessage, MapPiece, ServerMessage, Skin, WatchMessage,
+        WatcherMessage,
+    };
+    use tokio::{
+        sync::{broadcast, mpsc, oneshot},
+        time::Instant,
+    };
+
+    use super::{Game, LobbySettings, clean_appearance, sanitize_name, thumbnail};
+    use crate::{ClientUpdate, Player, config::CONFIG, lag::LagPolicy};
+
+    #[test]
+    fn thumbnails() {
+        let small = vec![MapPiece::Apple; 20 * 14];
+        assert_eq!(thumbnail(&small, (20, 14)).size, (20, 14));
+
+        let mut map = vec![MapPiece::Empty; 100 * 50];
+        map[99 + 49 * 100] = MapPiece::SnakeHead(1);
+        map[0] = MapPiece::Apple;
+        map[1] = MapPiece::Snake(2);
+        map[4] = MapPiece::Apple;
+        let thumb = thumbnail(&map, (100, 50));
+        assert_eq!(thumb.size, (25, 13));
+        assert_eq!(thumb.map[0], MapPiece::Snake(2));
+        assert_eq!(thumb.map[1], MapPiece::Apple);
+        assert_eq!(thumb.map[24 + 12 * 25], MapPiece::SnakeHead(1));
+        assert_eq!(
+            thumb.map.iter().filter(|&&p| p != MapPiece::Empty).count(),
+            3
+        );
+    }
+
+    #[test]
+    fn malicious_names() {
+        assert_eq!(sanitize_name("Alfa"), "Alfa");
+        assert_eq!(sanitize_name("Ålfa 🐍"), "Ålfa 🐍");
+        assert_eq!(
+            sanitize_name("<img src=x onerror=alert(1)>"),
+            "img src=x onerror=alert(1)"
+        );
+        assert_eq!(
+            sanitize_name("\"><script>alert('hi')</script>"),
+            "scriptalert(hi)/script"
+        );
+        assert_eq!(sanitize_name("a\nb\u{1b}[31mc"), "ab[31mc");
+        for invisible in ['\u{200b}', '\u{2060}', '\u{feff}', '\u{202e}'] {
+            assert_eq!(
+                sanitize_name(&format!("Al{invisible}fa{invisible}")),
+                "Alfa"
+            );
+        }
+        assert_eq!(sanitize_name("\u{202e}\u{200b}"), "snake");
+        assert_eq!(sanitize_name("  <>  "), "snake");
+        assert_eq!(sanitize_name(&"x".repeat(100)).len(), 32);
+    }
+
+    #[test]
+    fn appearance_colors() {
+        let colored = |color: &str| {
+            clean_appearance(Appearance {
+                color: Some(color.to_string()),
+                skin: Skin::Striped,
+            })
+        };
+        assert_eq!(colored("#FF8800").color.as_deref(), Some("#ff8800"));
+        assert_eq!(colored("#ff8800").skin, Skin::Striped);
+        assert_eq!(colored("red").color, None);
+        assert_eq!(colored("#ff88").color, None);
+        assert_eq!(colored("#ff880g").color, None);
+        assert_eq!(colored("#ff8800;x").color, None);
+        assert_eq!(colored("#ÿÿÿ").color, None);
+    }
+
+    #[tokio::test]
+    async fn muted_watchers() {
+        let (mut game, _, _) = Game::new(2908, LobbySettings::default());
+        let loud: SocketAddr = "127.0.0.1:1".parse().expect("bad address");
+        let other: SocketAddr = "127.0.0.1:2".parse().expect("bad address");
+        let mut heard = Vec::new();
+        for addr in [loud, other] {
+            let (send, recv) = broadcast::channel(16);
+            game.watchers.insert(addr, send);
+            heard.push(recv);
+        }
+        let mut last = |i: usize| iter::from_fn(|| heard[i].try_recv().ok()).last();
+
+        game.watcher_message(loud, WatcherMessage::SetName("Loud".to_string()));
+        game.admin(AdminCommand::Mute {
+            name: "<Loud>".to_string(),
+        })
+        .expect("failed muting");
+        // picking another name doesn't help
+        game.watcher_message(loud, WatcherMessage::SetName("Quiet".to_string()));
+        game.watcher_message(loud, WatcherMessage::Chat("hi".to_string()));
+        assert!(matches!(last(0), Some(WatchMessage::ChatRefused { .. })));
+        // and nobody else gets to use the muted one
+        game.watcher_message(other, WatcherMessage::SetName("Loud".to_string()));
+        assert!(matches!(last(1), Some(WatchMessage::ChatRefused { .. })));
+
+        game.admin(AdminCommand::Unmute {
+            name: "Loud".to_string(),
+        })
+        .expect("failed unmuting");
+        game.watcher_message(loud, WatcherMessage::Chat("hi".to_string()));
+        assert_eq!(
+            last(1),
+            Some(WatchMessage::Chat {
+                name: "Quiet".to_string(),
+                text: "hi".to_string(),
+            })
+        );
+    }
+
+    type Players = Vec<(SocketAddr, mpsc::Receiver<ServerMessage>)>;
+
+    /// A paused lobby ticking every 20ms under `policy`, with a prompt and a slow player.
+    async fn lagging(
+        lobby: usize,
+        policy: LagPolicy,
+    ) -> (
+        Game,
+        mpsc::Sender<(SocketAddr, ClientMessage)>,
+        mpsc::Sender<ClientUpdate>,
+        Players,
+    ) {
+        let (mut game, msgs, joins) = Game::new(
+            lobby,
+            LobbySettings {
+                paused: true,
+                tick_ms: Some(20),
+                ..Default::default()
+            },
+        );
+        game.lag_policy = policy;
+        let mut players = Vec::new();
+        for (port, name) in [(1, "Prompt"), (2, "Slow")] {
+            let addr = SocketAddr::from(([127, 0, 0, 1], port));
+            let player = Player {
+                name: name.to_string(),
+                registered: false,
+                appearance: Appearance::default(),
+            };
+            let (send, recv) = oneshot::channel();
+            joins
+                .send(ClientUpdate::Join(addr, player, send))
+                .await
+                .expect("lobby closed");
+            game.tick().await.expect("lobby failed");
+            let Ok(Ok(ticks)) = recv.await else {
+                panic!("{name} was turned away");
+            };
+            players.push((addr, ticks));
+        }
+        (game, msgs, joins, players)
+    }
+
+    /// Runs the lobby until it plays its next tick.
+    async fn played(game: &mut Game) {
+        let tick = game.tick;
+        while game.tick == tick {
+            game.tick().await.expect("lobby failed");
+        }
+    }
+
+    /// Answers `tick_id` as `who`, pausing the lobby so the interval can't play a tick
+    /// before the answer is in.
+    async fn answer(
+        game: &mut Game,
+        msgs: &mpsc::Sender<(SocketAddr, ClientMessage)>,
+        who: SocketAddr,
+        tick_id: u64,
+    ) {
+        game.paused = true;
+        msgs.send((who, ClientMessage::NoTurn(tick_id)))
+            .await
+            .expect("lobby closed");
+        while !game.msgs.is_empty() {
+            game.tick().await.expect("lobby failed");
+        }
+        game.paused = false;
+    }
+
+    /// The ids of the ticks a player was sent since last asked.
+    fn sent(ticks: &mut mpsc::Receiver<ServerMessage>) -> Vec<u64> {
+        iter::from_fn(|| ticks.try_recv().ok())
+            .filter_map(|msg| match msg {
+                ServerMessage::Tick { tick_id, .. } => Some(tick_id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn lockstep_waits_for_answers() {
+        let timeout = Duration::from_millis(200);
+        let (mut game, msgs, _joins, mut players) =
+            lagging(2910, LagPolicy::Lockstep { timeout }).await;
+        let (prompt, slow) = (players[0].0, players[1].0);
+        game.paused = false;
+        played(&mut game).await;
+        assert_eq!(sent(&mut players[1].1).len(), 1);
+
+        // without the slow player's answer the lobby holds out until the timeout
+        let tick_id = sent(&mut players[0].1)[0];
+        answer(&mut game, &msgs, prompt, tick_id).await;
+        let start = Instant::now();
+        played(&mut game).await;
+        assert!(start.elapsed() >= timeout);
+
+        // and goes on as soon as everyone answered
+        let tick_id = sent(&mut players[0].1)[0];
+        answer(&mut game, &msgs, prompt, tick_id).await;
+        while game.stalled.is_none() {
+            game.tick().await.expect("lobby failed");
+        }
+        let tick = game.tick;
+        answer(&mut game, &msgs, slow, tick_id).await;
+        assert_eq!(game.tick, tick + 1);
+    }
+
+    #[tokio::test]
+    async fn deadline_sends_every_tick() {
+        let (mut game, _msgs, _joins, mut players) = lagging(2911, LagPolicy::Deadline).await;
+        game.paused = false;
+        for _ in 0..3 {
+            played(&mut game).await;
+        }
+        assert_eq!(sent(&mut players[1].1).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn skip_slow_skips_ticks() {
+        let (mut game, msgs, _joins, mut players) = lagging(2912, LagPolicy::SkipSlow).await;
+        let slow = players[1].0;
+        game.paused = false;
+        for _ in 0..3 {
+            played(&mut game).await;
+        }
+        let tick_ids = sent(&mut players[1].1);
+        assert_eq!(tick_ids.len(), 3);
+        sent(&mut players[0].1);
+
+        // answering three ticks late skips the next two
+        answer(&mut game, &msgs, slow, tick_ids[0]).await;
+        for _ in 0..3 {
+            played(&mut game).await;
+        }
+        assert_eq!(sent(&mut players[0].1).len(), 3);
+        assert_eq!(sent(&mut players[1].1).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn debug_floods_are_kicked() {
+        let (mut game, msgs, _joins, mut players) = lagging(2913, LagPolicy::Deadline).await;
+        let flooder = players[0].0;
+        game.paused = false;
+        played(&mut game).await;
+        let tick_id = sent(&mut players[0].1)[0];
+
+        game.paused = true;
+        for _ in 0..=CONFIG.flood_limit {
+            let debug = ClientMessage::Debug {
+                tick_id,
+                annotations: Vec::new(),
+            };
+            msgs.send((flooder, debug)).await.expect("lobby closed");
+        }
+        while !game.msgs.is_empty() {
+            game.tick().await.expect("lobby failed");
+        }
+        assert!(!game.clients.contains_key(&flooder));
+        assert!(game.clients.contains_key(&players[1].0));
+    }
+}
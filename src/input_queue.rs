@@ -0,0 +1,55 @@
+use snakes_shared::ClientMessage;
+
+/// The commands a client has sent during the current tick.
+///
+/// Only the last command is kept, it wins the tick once it ends, see [`InputQueue::take`].
+/// How many were sent is still counted so flooding clients can be kicked.
+#[derive(Default)]
+pub struct InputQueue {
+    last: Option<ClientMessage>,
+    received: usize,
+}
+
+impl InputQueue {
+    /// Queues a command in place of the previous one, returning how many commands have
+    /// been received this tick.
+    pub fn push(&mut self, msg: ClientMessage) -> usize {
+        self.last = Some(msg);
+        self.received += 1;
+        self.received
+    }
+
+    /// How many commands have been received this tick, kept or not.
+    pub fn received(&self) -> usize {
+        self.received
+    }
+
+    /// Ends the tick, returning the command that wins it.
+    pub fn take(&mut self) -> Option<ClientMessage> {
+        self.received = 0;
+        self.last.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InputQueue;
+    use snakes_shared::{ClientMessage, TurnDirection};
+
+    #[test]
+    fn last_command_wins() {
+        let mut queue = InputQueue::default();
+        assert!(queue.take().is_none());
+        assert_eq!(queue.push(ClientMessage::NoTurn(1)), 1);
+        assert_eq!(
+            queue.push(ClientMessage::Turn(1, TurnDirection::Clockwise)),
+            2
+        );
+        assert_eq!(queue.push(ClientMessage::NoTurn(2)), 3);
+        assert_eq!(queue.received(), 3);
+        assert!(matches!(queue.take(), Some(ClientMessage::NoTurn(2))));
+        assert!(queue.take().is_none());
+        assert_eq!(queue.received(), 0);
+        assert_eq!(queue.push(ClientMessage::NoTurn(3)), 1);
+    }
+}
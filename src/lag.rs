@@ -0,0 +1,80 @@
+use std::{str::FromStr, time::Duration};
+
+/// Decides what a lobby does with clients that are slow to answer a `Tick`.
+///
+/// Configured with `SNAKES_LAG_POLICY`, which takes one of:
+/// - `lockstep` or `lockstep:<ms>`
+/// - `deadline`
+/// - `skip-slow` (default)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LagPolicy {
+    /// Wait for every client to answer the last tick before advancing the game,
+    /// but never longer than `timeout` past the regular tick interval.
+    Lockstep { timeout: Duration },
+    /// Advance the game on the tick interval and send every tick to every client,
+    /// late answers are simply applied on the next tick.
+    Deadline,
+    /// Advance the game on the tick interval, but a client whose last answer
+    /// was for a tick `n` ticks old is not sent the next `n - 1` ticks.
+    #[default]
+    SkipSlow,
+}
+
+impl LagPolicy {
+    const DEFAULT_LOCKSTEP_TIMEOUT: Duration = Duration::from_millis(500);
+}
+
+impl FromStr for LagPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = match s.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (s, None),
+        };
+        match (name, arg) {
+            ("lockstep", None) => Ok(Self::Lockstep {
+                timeout: Self::DEFAULT_LOCKSTEP_TIMEOUT,
+            }),
+            ("lockstep", Some(ms)) => Ok(Self::Lockstep {
+                timeout: Duration::from_millis(ms.parse()?),
+            }),
+            ("deadline", None) => Ok(Self::Deadline),
+            ("skip-slow", None) => Ok(Self::SkipSlow),
+            _ => Err(anyhow::Error::msg(format!("unknown lag policy: {s}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LagPolicy;
+    use std::time::Duration;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            "lockstep".parse::<LagPolicy>().ok(),
+            Some(LagPolicy::Lockstep {
+                timeout: Duration::from_millis(500)
+            })
+        );
+        assert_eq!(
+            "lockstep:120".parse::<LagPolicy>().ok(),
+            Some(LagPolicy::Lockstep {
+                timeout: Duration::from_millis(120)
+            })
+        );
+        assert_eq!(
+            "deadline".parse::<LagPolicy>().ok(),
+            Some(LagPolicy::Deadline)
+        );
+        assert_eq!(
+            "skip-slow".parse::<LagPolicy>().ok(),
+            Some(LagPolicy::SkipSlow)
+        );
+        assert!("deadline:5".parse::<LagPolicy>().is_err());
+        assert!("lockstep:soon".parse::<LagPolicy>().is_err());
+        assert!("wait".parse::<LagPolicy>().is_err());
+    }
+}
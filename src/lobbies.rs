@@ -0,0 +1,232 @@
+use axum::{
+    Json,
+    extract::{Path as UrlPath, Query},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use snakes_shared::{CreatedLobby, LobbySummary};
+use tokio::sync::oneshot;
+
+use crate::{
+    ClientUpdate, STATE, WSConnectInfo,
+    config::CONFIG,
+    create_lobby,
+    game::{FASTEST_TICK, LobbySettings},
+    metrics::METRICS,
+};
+
+/// Who may play in, watch and control a lobby.
+#[derive(Debug, Clone, Default)]
+pub struct Access {
+    /// Players need this code to join, the lobby is public without one.
+    pub join_code: Option<String>,
+    pub watchers: WatchAccess,
+    /// Allows pausing, stepping and slowing down the lobby, like an admin could.
+    pub host_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchAccess {
+    /// Watchers need the join code, just like players.
+    #[default]
+    Players,
+    Anyone,
+    /// Watchers need a password of their own.
+    Password(String),
+}
+
+impl Access {
+    pub fn is_public(&self) -> bool {
+        self.join_code.is_none()
+    }
+
+    pub fn may_join(&self, code: Option<&str>) -> bool {
+        self.join_code.is_none() || self.join_code.as_deref() == code
+    }
+
+    pub fn may_watch(&self, code: Option<&str>) -> bool {
+        match &self.watchers {
+            WatchAccess::Players => self.may_join(code),
+            WatchAccess::Anyone => true,
+            WatchAccess::Password(password) => Some(password.as_str()) == code,
+        }
+    }
+}
+
+/// Body of `POST /lobbies`.
+#[derive(Debug, Deserialize)]
+pub struct NewLobby {
+    /// Private lobbies get a join code and are left out of `GET /lobbies`.
+    #[serde(default)]
+    private: bool,
+    /// Use this as the join code instead of a generated one, makes the lobby private.
+    password: Option<String>,
+    #[serde(default)]
+    watchers: WatchAccess,
+    match_ticks: Option<usize>,
+    max_players: Option<usize>,
+    /// Start paused, for stepping through a game one tick at a time.
+    #[serde(default)]
+    paused: bool,
+    /// Tick at this rate instead of speeding up over time, [`FASTEST_TICK`] at most.
+    tick_ms: Option<u64>,
+}
+
+fn random_code(bytes: usize) -> String {
+    (0..bytes)
+        .map(|_| format!("{:02x}", rand::random::<u8>()))
+        .collect()
+}
+
+/// `POST /lobbies`, up to `SNAKES_MAX_CREATED_LOBBIES` of them at a time.
+pub async fn new_lobby(Json(new): Json<NewLobby>) -> Response {
+    // only lobbies made here have a host token
+    let created = STATE
+        .read()
+        .await
+        .values()
+        .filter(|info| info.access.host_token.is_some())
+        .count();
+    if created >= CONFIG.max_created_lobbies {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "too many lobbies are running, try again later",
+        )
+            .into_response();
+    }
+    let join_code = match new.password {
+        Some(password) => Some(password),
+        None if new.private => Some(random_code(4)),
+        None => None,
+    };
+    let host_token = random_code(16);
+    let lobby = create_lobby(LobbySettings {
+        match_ticks: new.match_ticks.or(CONFIG.match_ticks),
+        max_players: new.max_players.unwrap_or(CONFIG.max_players),
+        access: Access {
+            join_code: join_code.clone(),
+            watchers: new.watchers,
+            host_token: Some(host_token.clone()),
+        },
+        paused: new.paused,
+        tick_ms: new
+            .tick_ms
+            .map(|ms| ms.max(FASTEST_TICK.as_millis() as u64)),
+        ..Default::default()
+    })
+    .await;
+    info!(
+        "lobby {lobby}: created as a {} lobby",
+        if join_code.is_some() {
+            "private"
+        } else {
+            "public"
+        }
+    );
+    (
+        StatusCode::CREATED,
+        Json(CreatedLobby {
+            lobby,
+            join_code,
+            host_token,
+        }),
+    )
+        .into_response()
+}
+
+/// `GET /lobbies`, every running lobby except the private ones.
+pub async fn list_lobbies() -> Json<Vec<LobbySummary>> {
+    let mut lobbies = STATE
+        .read()
+        .await
+        .iter()
+        .filter(|(_, info)| info.access.is_public())
+        .map(|(&id, info)| {
+            let gauges = METRICS.lobby(id).unwrap_or_default();
+            let feed = info.summary.borrow().clone();
+            LobbySummary {
+                id,
+                players: gauges.players,
+                watchers: gauges.watchers,
+                tick: feed.tick,
+                tick_ms: feed.tick_ms,
+                paused: feed.paused,
+                map_size: feed.map_size,
+                thumbnail: feed.thumbnail,
+            }
+        })
+        .collect::<Vec<_>>();
+    lobbies.sort_by_key(|l| l.id);
+    Json(lobbies)
+}
+
+/// `GET /lobbies/{id}/state`, the board right now, for clients that would rather poll
+/// than watch. Takes the same `code` a watcher would need, and never starts a lobby.
+pub async fn lobby_state(
+    UrlPath(lobby): UrlPath<usize>,
+    Query(WSConnectInfo { code, .. }): Query<WSConnectInfo>,
+) -> Response {
+    let not_running = || {
+        (
+            StatusCode::NOT_FOUND,
+            format!("lobby {lobby} is not running"),
+        )
+            .into_response()
+    };
+    let Some(info) = STATE.read().await.get(&lobby).cloned() else {
+        return not_running();
+    };
+    if !info.access.may_watch(code.as_deref()) {
+        return (StatusCode::FORBIDDEN, "wrong watch password").into_response();
+    }
+    let (send, recv) = oneshot::channel();
+    if info
+        .client_update
+        .send(ClientUpdate::State(send))
+        .await
+        .is_err()
+    {
+        return not_running();
+    }
+    match recv.await {
+        Ok(update) => Json(update).into_response(),
+        Err(_) => not_running(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Access, WatchAccess};
+
+    #[test]
+    fn access() {
+        let public = Access::default();
+        assert!(public.may_join(None) && public.may_watch(None));
+
+        let private = Access {
+            join_code: Some("code".to_string()),
+            watchers: WatchAccess::Players,
+            host_token: None,
+        };
+        assert!(!private.may_join(None) && !private.may_join(Some("nope")));
+        assert!(private.may_join(Some("code")) && private.may_watch(Some("code")));
+        assert!(!private.may_watch(None));
+
+        let watch_password = Access {
+            join_code: Some("code".to_string()),
+            watchers: WatchAccess::Password("watch".to_string()),
+            host_token: None,
+        };
+        assert!(watch_password.may_watch(Some("watch")));
+        assert!(!watch_password.may_watch(Some("code")));
+
+        let open_to_watchers = Access {
+            join_code: Some("code".to_string()),
+            watchers: WatchAccess::Anyone,
+            host_token: None,
+        };
+        assert!(open_to_watchers.may_watch(None) && !open_to_watchers.may_join(None));
+    }
+}
@@ -1,38 +1,104 @@
 #![warn(clippy::print_stdout, clippy::print_stderr, clippy::unwrap_used)]
 #![feature(try_blocks)]
 
+use anyhow::Context;
 use axum::{
     Router,
     extract::{
         ConnectInfo, Query, WebSocketUpgrade,
-        ws::{Message, Utf8Bytes},
+        ws::{Message, Utf8Bytes, WebSocket},
     },
-    response::IntoResponse,
-    routing::{any, get},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{any, get, post},
 };
 use futures_util::{SinkExt as _, StreamExt as _};
 use schemars::schema_for;
 use serde::Deserialize;
-use snakes_shared::{ClientMessage, ServerMessage, WatchUpdate};
-use std::{collections::HashMap, env::args, io::Write, net::SocketAddr, sync::LazyLock};
-use tokio::sync::{RwLock, mpsc, oneshot};
+use snakes_shared::{
+    AdminCommand, Appearance, ClientMessage, ServerMessage, WatchMessage, WatchUpdate,
+    WatcherMessage,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    env::args,
+    io::Write,
+    net::SocketAddr,
+    path::Path,
+    sync::LazyLock,
+    time::Duration,
+};
+use tokio::sync::{
+    RwLock,
+    broadcast::{self, error::RecvError},
+    mpsc, oneshot, watch,
+};
 
 use crate::{
-    frontend::{index, serve_schema},
-    game::Game,
-    tick_buffer::TickBuffer,
+    admin::admin_command,
+    config::CONFIG,
+    frontend::{dashboard, index, serve_schema, static_asset},
+    game::{CHAT_BURST, CHAT_WINDOW, Game, LobbyFeed, LobbySettings, sanitize_name},
+    lobbies::{Access, list_lobbies, lobby_state, new_lobby},
+    metrics::{METRICS, Metrics, serve_metrics},
+    replays::{list_replays, serve_replay},
+    shutdown::{drain_on_signal, is_draining},
+    snapshot::{GameSnapshot, snapshot_lobby},
+    sse::watch_sse,
+    stats::{STATS, flush_periodically, serve_leaderboard, serve_ratings},
+    tournament::{create_tournament, serve_tournament},
 };
+mod accounts;
+mod admin;
+mod config;
 mod frontend;
 mod game;
+mod input_queue;
+mod lag;
+mod lobbies;
+mod matchmaking;
+mod metrics;
+mod rating;
+mod replays;
+mod shutdown;
+mod snapshot;
+mod sse;
+mod stats;
+mod storage;
+#[cfg(test)]
+mod tests;
 mod tick_buffer;
+mod tournament;
 
 enum ClientUpdate {
     Join(
         SocketAddr,
-        String,
-        oneshot::Sender<mpsc::UnboundedReceiver<ServerMessage>>,
+        Player,
+        oneshot::Sender<Result<mpsc::Receiver<ServerMessage>, JoinError>>,
     ),
-    Watcher(SocketAddr, mpsc::UnboundedSender<WatchUpdate>),
+    Left(SocketAddr),
+    /// Stop letting people in and close the lobby once the grace period is over.
+    Shutdown(Duration),
+    Snapshot(oneshot::Sender<anyhow::Result<()>>),
+    Watcher(SocketAddr, broadcast::Sender<WatchMessage>),
+    FromWatcher(SocketAddr, WatcherMessage),
+    Admin(AdminCommand, oneshot::Sender<Result<(), String>>),
+    WatcherLeft(SocketAddr),
+    /// The board as a watcher would see it right now.
+    State(oneshot::Sender<WatchUpdate>),
+}
+
+/// Why a lobby did not let a player in.
+enum JoinError {
+    Full { max_players: usize },
+    Rejected(String),
+}
+
+/// Who a client is, as settled during the handshake.
+struct Player {
+    name: String,
+    registered: bool,
+    appearance: Appearance,
 }
 
 #[allow(unused_imports)]
@@ -43,40 +109,139 @@ static STATE: LazyLock<RwLock<HashMap<usize, LobbyInfo>>> = LazyLock::new(Defaul
 
 #[derive(Clone)]
 struct LobbyInfo {
-    client_update: mpsc::UnboundedSender<ClientUpdate>,
-    msg_send: mpsc::UnboundedSender<(SocketAddr, ClientMessage)>,
+    client_update: mpsc::Sender<ClientUpdate>,
+    msg_send: mpsc::Sender<(SocketAddr, ClientMessage)>,
+    access: Access,
+    summary: watch::Receiver<LobbyFeed>,
 }
 
-async fn get_lobby_info(lobby: usize) -> LobbyInfo {
+/// Lobbies from this id up are handed out by the server and never started on demand,
+/// so nobody can take over the id of a private lobby once it has closed.
+const HANDED_OUT_LOBBIES: usize = 1_000_000;
+/// A watcher's messages beyond this many per [`CHAT_WINDOW`] never reach its lobby, which
+/// rate limits chat on its own but shouldn't have to wade through a flood to get there.
+const WATCHER_BURST: usize = 4 * CHAT_BURST;
+
+async fn get_lobby_info(lobby: usize) -> Option<LobbyInfo> {
     let read = STATE.read().await;
     if let Some(ls) = read.get(&lobby) {
-        return ls.clone();
+        return Some(ls.clone());
     }
     drop(read);
     let mut write = STATE.write().await;
-    let (mut game, msg_send, client_update) = Game::new(lobby);
+    if let Some(ls) = write.get(&lobby) {
+        return Some(ls.clone());
+    }
+    if lobby >= HANDED_OUT_LOBBIES {
+        return None;
+    }
+    let snapshot = match &CONFIG.snapshot_dir {
+        Some(dir) => snapshot::load(dir, lobby).await.unwrap_or_else(|e| {
+            error!("lobby {lobby}: failed to restore snapshot: {e:#}");
+            None
+        }),
+        None => None,
+    };
+    let ls = start_lobby(lobby, snapshot, &mut write);
+    drop(write);
+    Some(ls)
+}
+
+/// Starts a lobby anyone can join, picking up where `snapshot` left off.
+fn start_lobby(
+    lobby: usize,
+    snapshot: Option<GameSnapshot>,
+    state: &mut HashMap<usize, LobbyInfo>,
+) -> LobbyInfo {
+    let (mut game, msg_send, client_update) = Game::new(lobby, LobbySettings::default());
+    if let Some(snapshot) = snapshot {
+        game.restore(snapshot);
+    }
     let ls = LobbyInfo {
         client_update,
         msg_send,
+        access: Access::default(),
+        summary: game.summary(),
+    };
+    state.insert(lobby, ls.clone());
+    run_lobby(lobby, game);
+    ls
+}
+
+/// Starts every lobby saved in `dir`, so they are listed again right after a restart
+/// instead of waiting for one of their bots to come back.
+async fn restore_lobbies(dir: &Path) {
+    let lobbies = match snapshot::saved(dir).await {
+        Ok(lobbies) => lobbies,
+        Err(e) => {
+            error!("failed to look for saved lobbies: {e:#}");
+            return;
+        }
     };
-    write.insert(lobby, ls.clone());
+    for lobby in lobbies.into_iter().filter(|&l| l < HANDED_OUT_LOBBIES) {
+        let snapshot = match snapshot::load(dir, lobby).await {
+            Ok(Some(snapshot)) => snapshot,
+            Ok(None) => continue,
+            Err(e) => {
+                error!("lobby {lobby}: failed to restore snapshot: {e:#}");
+                continue;
+            }
+        };
+        let mut write = STATE.write().await;
+        if !write.contains_key(&lobby) {
+            start_lobby(lobby, Some(snapshot), &mut write);
+        }
+    }
+}
+
+/// Starts a lobby with its own settings under a free id, for lobbies that are
+/// handed out to players rather than picked by them.
+async fn create_lobby(settings: LobbySettings) -> usize {
+    let mut write = STATE.write().await;
+    let lobby = loop {
+        let lobby = rand::random_range(HANDED_OUT_LOBBIES..1_000_000_000);
+        if !write.contains_key(&lobby) {
+            break lobby;
+        }
+    };
+    let access = settings.access.clone();
+    let (game, msg_send, client_update) = Game::new(lobby, settings);
+    write.insert(
+        lobby,
+        LobbyInfo {
+            client_update,
+            msg_send,
+            access,
+            summary: game.summary(),
+        },
+    );
     drop(write);
+    run_lobby(lobby, game);
+    lobby
+}
 
+fn run_lobby(lobby: usize, mut game: Game) {
     tokio::spawn(async move {
         loop {
             let Err(e) = game.tick().await else {
                 continue;
             };
             warn!("lobby {lobby}: error {e}");
-            STATE.write().await.remove(&lobby);
             break;
         }
+        if let Err(e) = game.save().await {
+            error!("lobby {lobby}: failed to save snapshot: {e:#}");
+        }
+        drop(game);
+        STATE.write().await.remove(&lobby);
     });
-    ls
 }
 
 #[tokio::main]
 async fn main() {
+    if args().nth(1).is_some_and(|a| a == "token") {
+        std::process::exit(accounts::cli(&args().skip(2).collect::<Vec<_>>()).await)
+    }
     let schema = args().nth(1).map(|v| v == "schema").unwrap_or_default();
     if cfg!(debug_assertions) || schema {
         let schema = serde_json::to_string_pretty(&schema_for!(ServerMessage))
@@ -95,123 +260,380 @@ async fn main() {
         .with_env_filter("none,angry_snakes=trace")
         .finish();
     tracing::subscriber::set_global_default(subscriber).expect("failed set up tracing");
-
-    let app = Router::new()
-        .route("/", get(index))
-        .route("/schema", get(serve_schema))
-        .route("/watch", any(watch_ws_handler))
-        .route("/ws", any(game_ws_handler));
+    info!("lag policy: {:?}", CONFIG.lag_policy);
+    match &CONFIG.snapshot_dir {
+        Some(dir) => info!("lobbies are saved to and restored from {}", dir.display()),
+        None => info!("lobby snapshots are disabled"),
+    }
+    LazyLock::force(&STATS);
+    tokio::spawn(flush_periodically());
+    if let Some(dir) = &CONFIG.snapshot_dir {
+        restore_lobbies(dir).await;
+    }
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:8000")
         .await
         .expect("failed to bind address");
     axum::serve(
         listener,
-        app.into_make_service_with_connect_info::<SocketAddr>(),
+        app().into_make_service_with_connect_info::<SocketAddr>(),
     )
+    .with_graceful_shutdown(drain_on_signal())
     .await
     .expect("server crash");
+    if let Err(e) = STATS.flush().await {
+        error!("failed to save player stats: {e:#}");
+    }
+}
+
+fn app() -> Router {
+    Router::new()
+        .route("/", get(index))
+        .route("/dashboard", get(dashboard))
+        .route("/schema", get(serve_schema))
+        .route("/static/{file}", get(static_asset))
+        .route("/metrics", get(serve_metrics))
+        .route("/leaderboard", get(serve_leaderboard))
+        .route("/ratings", get(serve_ratings))
+        .route("/lobbies", get(list_lobbies).post(new_lobby))
+        .route("/lobbies/{id}/snapshot", post(snapshot_lobby))
+        .route("/lobbies/{id}/state", get(lobby_state))
+        .route("/admin/lobbies/{id}", post(admin_command))
+        .route("/tournaments", post(create_tournament))
+        .route("/tournaments/{id}", get(serve_tournament))
+        .route("/replays", get(list_replays))
+        .route("/replays/{id}", get(serve_replay))
+        .route("/watch", any(watch_ws_handler))
+        .route("/watch/sse", get(watch_sse))
+        .route("/ws", any(game_ws_handler))
 }
 
 #[derive(Deserialize)]
 struct WSConnectInfo {
     lobby: Option<usize>,
+    token: Option<String>,
+    /// Play the next match of a tournament instead of joining `lobby`.
+    tournament: Option<usize>,
+    /// Wait for a match with similarly rated players instead of joining `lobby`,
+    /// `ranked` is the only queue.
+    queue: Option<String>,
+    /// The join code of a private lobby, or its watch password for watchers.
+    code: Option<String>,
+    /// Watch instead of being turned away when the lobby is full.
+    #[serde(default)]
+    watch_when_full: bool,
+    /// Watchers asking for `v=2` get every [`WatchMessage`], others only the bare
+    /// `WatchUpdate`s `/watch` has always sent.
+    #[serde(default)]
+    v: u32,
 }
 
 async fn watch_ws_handler(
-    Query(WSConnectInfo { lobby }): Query<WSConnectInfo>,
+    Query(WSConnectInfo { lobby, code, v, .. }): Query<WSConnectInfo>,
     ws: WebSocketUpgrade,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-) -> impl IntoResponse {
+) -> Response {
     let lobby = lobby.unwrap_or_default();
-    ws.on_upgrade(async move |socket| {
-        let socket = socket;
-        let who = addr;
-        let LobbyInfo { client_update, .. } = get_lobby_info(lobby).await;
-        let (pipe_send, mut pipe) = mpsc::unbounded_channel();
+    let client_update = match watchable(lobby, code.as_deref()).await {
+        Ok(client_update) => client_update,
+        Err(response) => return response,
+    };
+    ws.on_upgrade(async move |socket| watch(socket, addr, lobby, client_update, v >= 2).await)
+        .into_response()
+}
 
-        client_update
-            .send(ClientUpdate::Watcher(who, pipe_send))
-            .expect("game server dead");
-        let (mut sender, mut receiver) = socket.split();
-        tokio::spawn(async move {
-            loop {
-                let msg = tokio::select! {
-                    _ = receiver.next() => {
-                        break;
+/// How to reach `lobby`, if it is running and `code` lets people watch it.
+async fn watchable(
+    lobby: usize,
+    code: Option<&str>,
+) -> Result<mpsc::Sender<ClientUpdate>, Response> {
+    if is_draining() {
+        return Err((StatusCode::SERVICE_UNAVAILABLE, "server is shutting down").into_response());
+    }
+    let Some(LobbyInfo {
+        client_update,
+        access,
+        ..
+    }) = get_lobby_info(lobby).await
+    else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("lobby {lobby} is not running"),
+        )
+            .into_response());
+    };
+    if !access.may_watch(code) {
+        return Err((StatusCode::FORBIDDEN, "wrong watch password").into_response());
+    }
+    Ok(client_update)
+}
+
+/// Registers `who` as a watcher of `lobby`, starting with the board as it is right now.
+/// The lobby forgets about it once the receiver is dropped.
+async fn register_watcher(
+    who: SocketAddr,
+    lobby: usize,
+    client_update: &mpsc::Sender<ClientUpdate>,
+) -> Option<broadcast::Receiver<WatchMessage>> {
+    let (pipe_send, pipe) = broadcast::channel(CONFIG.watcher_buffer);
+    if client_update
+        .send(ClientUpdate::Watcher(who, pipe_send))
+        .await
+        .is_err()
+    {
+        error!("{who}: lobby {lobby} closed before the watcher could join");
+        return None;
+    }
+    Some(pipe)
+}
+
+/// Registers `socket` as a watcher of `lobby` and streams it the game, `tagged` watchers
+/// get chat and the rest as well.
+async fn watch(
+    socket: WebSocket,
+    who: SocketAddr,
+    lobby: usize,
+    client_update: mpsc::Sender<ClientUpdate>,
+    tagged: bool,
+) {
+    let Some(mut pipe) = register_watcher(who, lobby, &client_update).await else {
+        return;
+    };
+    let (mut sender, mut receiver) = socket.split();
+    tokio::spawn(async move {
+        let mut recent = VecDeque::new();
+        loop {
+            let msg = tokio::select! {
+                msg = receiver.next() => match msg {
+                    Some(Ok(Message::Text(text))) => {
+                        let now = tokio::time::Instant::now();
+                        recent.retain(|&at| now - at < CHAT_WINDOW);
+                        if recent.len() >= WATCHER_BURST {
+                            debug!("{who}: dropped a watcher message, too many");
+                            continue;
+                        }
+                        recent.push_back(now);
+                        match serde_json::from_str(text.as_str()) {
+                            Ok(msg) => {
+                                // a busy lobby drops it rather than holding up everyone else
+                                _ = client_update.try_send(ClientUpdate::FromWatcher(who, msg));
+                            }
+                            Err(e) => debug!("{who}: invalid watcher message: {e}"),
+                        }
+                        continue;
                     }
-                    msg = pipe.recv() => {
-                        let Some(msg) = msg else { break };
-                        msg
+                    Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                },
+                msg = pipe.recv() => match msg {
+                    Ok(WatchMessage::Update(update)) if !tagged => {
+                        serde_json::to_string(&update)
                     }
-                };
-                let data = serde_json::to_string(&msg).expect("failed encoding");
+                    Ok(_) if !tagged => continue,
+                    Ok(msg) => serde_json::to_string(&msg),
+                    Err(RecvError::Lagged(n)) => {
+                        Metrics::add(&METRICS.watcher_frames_dropped, n);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            };
+            let e: anyhow::Result<()> = try {
+                let data = msg.context("failed encoding")?;
                 sender
                     .send(Message::Text(Utf8Bytes::from(data)))
                     .await
-                    .expect("failed to send to client");
+                    .context("failed sending")?
+            };
+            if let Err(e) = e {
+                debug!("{who} watch error: {e}");
+                break;
             }
-        });
-    })
+        }
+        _ = client_update.send(ClientUpdate::WatcherLeft(who)).await;
+        info!("{who}: closed watch loop");
+    });
+}
+
+async fn recv_client_message(socket: &mut WebSocket) -> Option<ClientMessage> {
+    socket
+        .recv()
+        .await
+        .and_then(|s| s.ok())
+        .and_then(|s| match s {
+            Message::Text(bytes) => Some(bytes),
+            _ => None,
+        })
+        .and_then(|s| serde_json::from_slice::<ClientMessage>(s.as_bytes()).ok())
+}
+
+async fn send_message(socket: &mut WebSocket, msg: &ServerMessage) -> anyhow::Result<()> {
+    let json = serde_json::to_string(msg).context("failed encoding")?;
+    socket
+        .send(Message::Text(Utf8Bytes::from(json)))
+        .await
+        .context("failed sending")
+}
+
+/// Tells a client why it can't join before closing the connection.
+async fn reject(socket: WebSocket, reason: String) {
+    refuse(socket, ServerMessage::Kicked { reason }).await
+}
+
+async fn refuse(mut socket: WebSocket, msg: ServerMessage) {
+    let e: anyhow::Result<()> = try {
+        send_message(&mut socket, &msg).await?;
+        socket.close().await.context("failed closing")?
+    };
+    if let Err(e) = e {
+        debug!("failed to reject client: {e}");
+    }
 }
 
 async fn game_ws_handler(
-    Query(WSConnectInfo { lobby }): Query<WSConnectInfo>,
+    Query(WSConnectInfo {
+        lobby,
+        token,
+        tournament,
+        queue,
+        code,
+        watch_when_full,
+        v,
+    }): Query<WSConnectInfo>,
     ws: WebSocketUpgrade,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-) -> impl IntoResponse {
+) -> Response {
+    if is_draining() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "server is shutting down").into_response();
+    }
     let lobby = lobby.unwrap_or_default();
     ws.on_upgrade(async move |socket| {
         let mut socket = socket;
         let who = addr;
-        let LobbyInfo {
+        let mut token = token;
+        let mut appearance = Appearance::default();
+        let name = loop {
+            match recv_client_message(&mut socket).await {
+                Some(ClientMessage::Authenticate(t)) => token = Some(t),
+                Some(ClientMessage::Appearance(a)) => appearance = a,
+                Some(ClientMessage::SetName(name)) => break name,
+                _ => {
+                    error!("client {who} did not send a proper handshake");
+                    return;
+                }
+            }
+        };
+        // before checking it against the reserved names, which the lobby would otherwise
+        // let through once it cleaned up something like `Alfa<`
+        let name = sanitize_name(&name);
+        let registered = match accounts::verify(&name, token.as_deref()).await {
+            Ok(registered) => registered,
+            Err(reason) => {
+                info!("client {who} may not use {name}: {reason}");
+                reject(socket, reason).await;
+                return;
+            }
+        };
+        let (lobby, info) = match (tournament, queue) {
+            (None, None) => (lobby, get_lobby_info(lobby).await),
+            (None, Some(queue)) if queue != "ranked" => {
+                reject(socket, format!("there is no {queue} queue")).await;
+                return;
+            }
+            _ if !registered => {
+                let reason = "tournaments and ranked matches are for registered bots";
+                reject(socket, reason.to_string()).await;
+                return;
+            }
+            (None, Some(_)) => {
+                match matchmaking::find_match(&mut socket, who, &name, STATS.rating(&name)).await {
+                    Ok(lobby) => (lobby, STATE.read().await.get(&lobby).cloned()),
+                    Err(reason) => {
+                        info!("{who}: {reason}");
+                        reject(socket, reason).await;
+                        return;
+                    }
+                }
+            }
+            (Some(id), _) => match tournament::assignment(id, &name).await {
+                Ok(lobby) => (lobby, STATE.read().await.get(&lobby).cloned()),
+                Err(reason) => {
+                    reject(socket, reason).await;
+                    return;
+                }
+            },
+        };
+        let Some(LobbyInfo {
             client_update,
             msg_send,
+            access,
             ..
-        } = get_lobby_info(lobby).await;
-
-        let Some(ClientMessage::SetName(name)) = socket
-            .recv()
-            .await
-            .and_then(|s| s.ok())
-            .and_then(|s| match s {
-                Message::Text(bytes) => Some(bytes),
-                _ => None,
-            })
-            .and_then(|s| serde_json::from_slice::<ClientMessage>(s.as_bytes()).ok())
+        }) = info
         else {
-            error!("client {who} did not send a proper handshake");
+            reject(socket, format!("lobby {lobby} is not running")).await;
             return;
         };
+        if !access.may_join(code.as_deref()) {
+            info!("client {who} got the join code of lobby {lobby} wrong");
+            reject(socket, format!("lobby {lobby} is private, wrong join code")).await;
+            return;
+        }
         let (pipe_send, pipe_recv) = oneshot::channel();
         if client_update
-            .send(ClientUpdate::Join(who, name, pipe_send))
+            .send(ClientUpdate::Join(
+                who,
+                Player {
+                    name: name.clone(),
+                    registered,
+                    appearance,
+                },
+                pipe_send,
+            ))
+            .await
             .is_err()
         {
             return;
         }
-        let mut pipe = pipe_recv.await.expect("failed getting pipe from server");
-        let (mut sender, mut receiver) = socket.split();
-        tokio::spawn(async move {
-            while let Some(msg) = pipe.recv().await {
-                let e: anyhow::Result<()> = try {
-                    let json = serde_json::to_string(&msg)?;
-                    sender.send(Message::Text(Utf8Bytes::from(json))).await?
-                };
-                if let Err(e) = e {
-                    error!("{who} send error: {e}");
-                    break;
+        let mut pipe = match pipe_recv.await {
+            Ok(Ok(pipe)) => pipe,
+            Ok(Err(JoinError::Rejected(reason))) => {
+                info!("{who}: lobby {lobby} turned {name} away: {reason}");
+                reject(socket, reason).await;
+                return;
+            }
+            Ok(Err(JoinError::Full { max_players })) => {
+                let msg = ServerMessage::LobbyFull { max_players };
+                if watch_when_full && access.may_watch(code.as_deref()) {
+                    info!("{who}: lobby {lobby} is full, {name} watches instead");
+                    if let Err(e) = send_message(&mut socket, &msg).await {
+                        debug!("{who}: {e}");
+                        return;
+                    }
+                    watch(socket, who, lobby, client_update, v >= 2).await;
+                } else {
+                    info!("{who}: lobby {lobby} is full, turned {name} away");
+                    refuse(socket, msg).await;
                 }
+                return;
             }
-            info!("{who}: closed send loop");
-        });
-        tokio::spawn(async move {
+            Err(_) => {
+                error!("{who}: lobby {lobby} closed before the client could join");
+                return;
+            }
+        };
+        let (mut sender, mut receiver) = socket.split();
+        let leave = client_update.clone();
+        let recv_loop = tokio::spawn(async move {
             while let Some(Ok(msg)) = receiver.next().await {
                 let e: anyhow::Result<()> = try {
                     match msg {
                         Message::Text(bytes) => {
-                            let msg = serde_json::from_slice::<ClientMessage>(bytes.as_bytes())?;
-                            msg_send.send((who, msg))?;
+                            let msg = serde_json::from_slice::<ClientMessage>(bytes.as_bytes())
+                                .context("failed decoding")?;
+                            msg_send
+                                .send((who, msg))
+                                .await
+                                .context("game server dead")?;
                         }
                         Message::Close(_close_frame) => break,
                         x => Err(anyhow::Error::msg(format!("{x:?}",)))?,
@@ -222,7 +644,29 @@ async fn game_ws_handler(
                     break;
                 }
             }
+            _ = leave.send(ClientUpdate::Left(who)).await;
             info!("{who}: closed recv loop");
         });
+        tokio::spawn(async move {
+            while let Some(msg) = pipe.recv().await {
+                let e: anyhow::Result<()> = try {
+                    let json = serde_json::to_string(&msg).context("failed encoding")?;
+                    sender
+                        .send(Message::Text(Utf8Bytes::from(json)))
+                        .await
+                        .context("failed sending")?
+                };
+                if let Err(e) = e {
+                    error!("{who} send error: {e}");
+                    break;
+                }
+            }
+            // either the game or the client is gone, tear down the whole connection
+            _ = sender.close().await;
+            recv_loop.abort();
+            _ = client_update.send(ClientUpdate::Left(who)).await;
+            info!("{who}: closed send loop");
+        });
     })
+    .into_response()
 }
@@ -0,0 +1,164 @@
+use std::{
+    net::SocketAddr,
+    sync::{LazyLock, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use axum::extract::ws::WebSocket;
+use snakes_shared::ServerMessage;
+use tokio::{
+    sync::oneshot,
+    time::{Instant, interval},
+};
+
+use crate::{config::CONFIG, create_lobby, game::LobbySettings, send_message};
+
+/// Length of a ranked match when `SNAKES_MATCH_TICKS` isn't set.
+const RANKED_MATCH_TICKS: usize = 500;
+/// How far apart ratings may be for players who just joined the queue.
+const BASE_WINDOW: f64 = 100.0;
+/// How much further apart ratings may be for every second spent waiting.
+const WINDOW_GROWTH: f64 = 10.0;
+
+static QUEUE: LazyLock<Mutex<Vec<Waiting>>> = LazyLock::new(Default::default);
+
+fn queue() -> MutexGuard<'static, Vec<Waiting>> {
+    QUEUE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+struct Waiting {
+    who: SocketAddr,
+    name: String,
+    rating: f64,
+    since: Instant,
+    found: oneshot::Sender<usize>,
+}
+
+fn window(waited: Duration) -> f64 {
+    BASE_WINDOW + WINDOW_GROWTH * waited.as_secs_f64()
+}
+
+/// Picks `size` players out of `(rating, waited)` whose ratings are close enough for
+/// every one of them, preferring the group with whoever has waited the longest.
+fn pick(queue: &[(f64, Duration)], size: usize) -> Option<Vec<usize>> {
+    let mut by_rating = (0..queue.len()).collect::<Vec<_>>();
+    by_rating.sort_by(|&a, &b| queue[a].0.total_cmp(&queue[b].0));
+    by_rating
+        .windows(size)
+        .filter(|group| {
+            let spread = queue[group[size - 1]].0 - queue[group[0]].0;
+            group.iter().all(|&i| spread <= window(queue[i].1))
+        })
+        .max_by_key(|group| group.iter().map(|&i| queue[i].1).max())
+        .map(<[usize]>::to_vec)
+}
+
+/// Starts a lobby for every group of queued players that can be matched.
+async fn make_matches() {
+    loop {
+        let group = {
+            let mut queue = queue();
+            let now = Instant::now();
+            let waiting = queue
+                .iter()
+                .map(|w| (w.rating, now - w.since))
+                .collect::<Vec<_>>();
+            let Some(mut picked) = pick(&waiting, CONFIG.queue_size) else {
+                return;
+            };
+            picked.sort_unstable_by(|a, b| b.cmp(a));
+            picked
+                .into_iter()
+                .map(|i| queue.remove(i))
+                .collect::<Vec<_>>()
+        };
+        let names = group.iter().map(|w| w.name.clone()).collect::<Vec<_>>();
+        let lobby = create_lobby(LobbySettings {
+            match_ticks: Some(CONFIG.match_ticks.unwrap_or(RANKED_MATCH_TICKS)),
+            max_players: names.len(),
+            roster: Some(names.clone()),
+            single_match: true,
+            ..Default::default()
+        })
+        .await;
+        info!("matched {} in lobby {lobby}", names.join(", "));
+        for w in group {
+            _ = w.found.send(lobby);
+        }
+    }
+}
+
+/// Keeps `name` in the ranked queue until a match is found, then tells the client
+/// about it and returns the lobby. Fails when the client leaves the queue.
+pub async fn find_match(
+    socket: &mut WebSocket,
+    who: SocketAddr,
+    name: &str,
+    rating: f64,
+) -> Result<usize, String> {
+    let (found, mut recv) = oneshot::channel();
+    {
+        let mut queue = queue();
+        if queue.iter().any(|w| w.name == name) {
+            return Err(format!("{name} is already in the queue"));
+        }
+        queue.push(Waiting {
+            who,
+            name: name.to_string(),
+            rating,
+            since: Instant::now(),
+            found,
+        });
+    }
+    info!("{who}: {name} ({rating:.0}) is looking for a ranked match");
+    let mut retry = interval(Duration::from_secs(1));
+    let lobby = loop {
+        tokio::select! {
+            lobby = &mut recv => break lobby.map_err(|_| "matchmaking failed".to_string())?,
+            _ = retry.tick() => make_matches().await,
+            msg = socket.recv() => {
+                if !matches!(msg, Some(Ok(_))) {
+                    queue().retain(|w| w.who != who);
+                    return Err(format!("{name} left the queue"));
+                }
+            }
+        }
+    };
+    send_message(socket, &ServerMessage::MatchFound { lobby })
+        .await
+        .map_err(|e| format!("{e}"))?;
+    Ok(lobby)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::pick;
+
+    #[test]
+    fn rating_window() {
+        let now = Duration::ZERO;
+        let queue = [(1500.0, now), (1900.0, now), (1550.0, now)];
+        assert_eq!(pick(&queue, 2), Some(vec![0, 2]));
+        assert_eq!(pick(&queue, 3), None);
+
+        // the window grows the longer someone waits
+        let queue = [(1500.0, Duration::from_secs(60)), (1900.0, now)];
+        assert_eq!(pick(&queue, 2), None);
+        let queue = [
+            (1500.0, Duration::from_secs(60)),
+            (1900.0, Duration::from_secs(40)),
+        ];
+        assert_eq!(pick(&queue, 2), Some(vec![0, 1]));
+
+        // the longest waiting player goes first
+        let queue = [
+            (1500.0, now),
+            (1510.0, now),
+            (1800.0, Duration::from_secs(5)),
+            (1810.0, now),
+        ];
+        assert_eq!(pick(&queue, 2), Some(vec![2, 3]));
+    }
+}
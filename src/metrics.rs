@@ -0,0 +1,87 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Mutex, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+/// Counters exposed on `/metrics`, in the Prometheus text format.
+pub static METRICS: Metrics = Metrics {
+    watcher_frames_dropped: AtomicU64::new(0),
+    players_dropped_slow: AtomicU64::new(0),
+    lobbies: Mutex::new(BTreeMap::new()),
+};
+
+pub struct Metrics {
+    /// `WatchUpdate`s thrown away because a watcher did not keep up.
+    pub watcher_frames_dropped: AtomicU64,
+    /// Players disconnected because their outgoing queue filled up.
+    pub players_dropped_slow: AtomicU64,
+    lobbies: Mutex<BTreeMap<usize, LobbyGauges>>,
+}
+
+/// How many sockets a running lobby currently knows about.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LobbyGauges {
+    pub players: usize,
+    pub watchers: usize,
+}
+
+impl Metrics {
+    pub fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn lobbies(&self) -> std::sync::MutexGuard<'_, BTreeMap<usize, LobbyGauges>> {
+        self.lobbies.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn set_lobby(&self, lobby: usize, gauges: LobbyGauges) {
+        self.lobbies().insert(lobby, gauges);
+    }
+
+    pub fn remove_lobby(&self, lobby: usize) {
+        self.lobbies().remove(&lobby);
+    }
+
+    pub fn lobby(&self, lobby: usize) -> Option<LobbyGauges> {
+        self.lobbies().get(&lobby).copied()
+    }
+}
+
+pub async fn serve_metrics() -> String {
+    let counters = [
+        (
+            "snakes_watcher_frames_dropped_total",
+            &METRICS.watcher_frames_dropped,
+        ),
+        (
+            "snakes_players_dropped_slow_total",
+            &METRICS.players_dropped_slow,
+        ),
+    ];
+    let mut out = String::new();
+    for (name, counter) in counters {
+        out += &format!(
+            "# TYPE {name} counter\n{name} {}\n",
+            counter.load(Ordering::Relaxed)
+        );
+    }
+    let lobbies = METRICS.lobbies();
+    out += "# TYPE snakes_lobby_players gauge\n";
+    for (lobby, gauges) in lobbies.iter() {
+        out += &format!(
+            "snakes_lobby_players{{lobby=\"{lobby}\"}} {}\n",
+            gauges.players
+        );
+    }
+    out += "# TYPE snakes_lobby_watchers gauge\n";
+    for (lobby, gauges) in lobbies.iter() {
+        out += &format!(
+            "snakes_lobby_watchers{{lobby=\"{lobby}\"}} {}\n",
+            gauges.watchers
+        );
+    }
+    out
+}
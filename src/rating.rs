@@ -0,0 +1,56 @@
+/// The rating every registered bot starts out with.
+pub const INITIAL_RATING: f64 = 1500.0;
+/// How much a single match can move a rating.
+const K: f64 = 32.0;
+
+/// Pairwise Elo over the final standings of a match.
+///
+/// Every player is scored against every other player as if they had played a 1v1 game,
+/// winning it when placing higher and drawing on the same place. The change is divided
+/// by the number of opponents so a match weighs the same no matter how many played.
+/// Takes `(rating, place)` for each player and returns their new ratings in the same order.
+pub fn update(players: &[(f64, usize)]) -> Vec<f64> {
+    let opponents = players.len().saturating_sub(1).max(1) as f64;
+    players
+        .iter()
+        .enumerate()
+        .map(|(i, &(rating, place))| {
+            let change = players
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, &(other, other_place))| {
+                    let expected = 1.0 / (1.0 + 10f64.powf((other - rating) / 400.0));
+                    let score = match place.cmp(&other_place) {
+                        std::cmp::Ordering::Less => 1.0,
+                        std::cmp::Ordering::Equal => 0.5,
+                        std::cmp::Ordering::Greater => 0.0,
+                    };
+                    score - expected
+                })
+                .sum::<f64>();
+            rating + K * change / opponents
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::update;
+
+    #[test]
+    fn elo() {
+        assert_eq!(update(&[(1500.0, 1), (1500.0, 2)]), [1516.0, 1484.0]);
+        assert_eq!(update(&[(1500.0, 1), (1500.0, 1)]), [1500.0, 1500.0]);
+
+        let new = update(&[(1400.0, 1), (1600.0, 2), (1500.0, 3)]);
+        assert!(new[0] > 1400.0 && new[1] < 1600.0 && new[2] < 1500.0);
+        let total = new.iter().sum::<f64>();
+        assert!((total - 4500.0).abs() < 1e-9);
+
+        // an upset moves ratings more than the expected result
+        let upset = update(&[(1300.0, 1), (1700.0, 2)])[0] - 1300.0;
+        let expected = update(&[(1700.0, 1), (1300.0, 2)])[0] - 1700.0;
+        assert!(upset > expected);
+    }
+}
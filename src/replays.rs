@@ -0,0 +1,88 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, LazyLock, Mutex, MutexGuard, PoisonError},
+};
+
+use axum::{
+    Json,
+    extract::Path as UrlPath,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use snakes_shared::{Replay, ReplaySummary, Standing, WatchUpdate};
+
+/// How many of the latest matches can be replayed.
+const KEPT_REPLAYS: usize = 50;
+/// Matches are only recorded until their frames take up this much as JSON, which is also
+/// about the size of `GET /replays/{id}`.
+pub const MAX_REPLAY_BYTES: usize = 4 << 20;
+/// Older replays are dropped once all of them together are bigger than this.
+const KEPT_REPLAY_BYTES: usize = 64 << 20;
+
+struct Kept {
+    replay: Arc<Replay>,
+    /// The size of its frames as JSON.
+    bytes: usize,
+}
+
+static REPLAYS: LazyLock<Mutex<VecDeque<Kept>>> = LazyLock::new(Default::default);
+
+fn replays() -> MutexGuard<'static, VecDeque<Kept>> {
+    REPLAYS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Keeps a finished match around for replaying, dropping the oldest ones to make room.
+pub fn store(
+    lobby: usize,
+    standings: Vec<Standing>,
+    frames: Vec<WatchUpdate>,
+    bytes: usize,
+) -> usize {
+    let mut replays = replays();
+    let id = replays.back().map_or(1, |kept| kept.replay.id + 1);
+    let mut total = bytes + replays.iter().map(|kept| kept.bytes).sum::<usize>();
+    while replays.len() >= KEPT_REPLAYS || (total > KEPT_REPLAY_BYTES && !replays.is_empty()) {
+        if let Some(oldest) = replays.pop_front() {
+            total -= oldest.bytes;
+        }
+    }
+    replays.push_back(Kept {
+        replay: Arc::new(Replay {
+            id,
+            lobby,
+            standings,
+            frames,
+        }),
+        bytes,
+    });
+    id
+}
+
+/// `GET /replays`, the latest first.
+pub async fn list_replays() -> Json<Vec<ReplaySummary>> {
+    Json(
+        replays()
+            .iter()
+            .rev()
+            .map(|kept| &kept.replay)
+            .map(|r| ReplaySummary {
+                id: r.id,
+                lobby: r.lobby,
+                ticks: r.frames.len(),
+                standings: r.standings.clone(),
+            })
+            .collect(),
+    )
+}
+
+/// `GET /replays/{id}`
+pub async fn serve_replay(UrlPath(id): UrlPath<usize>) -> Response {
+    let replay = replays()
+        .iter()
+        .find(|kept| kept.replay.id == id)
+        .map(|kept| kept.replay.clone());
+    match replay {
+        Some(replay) => Json(&*replay).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("no replay {id}")).into_response(),
+    }
+}
@@ -0,0 +1,81 @@
+use std::{
+    collections::HashSet,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+use crate::{ClientUpdate, STATE, config::CONFIG};
+
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+/// Whether the server is shutting down and should refuse new connections.
+pub fn is_draining() -> bool {
+    DRAINING.load(Ordering::Relaxed)
+}
+
+/// Resolves once a shutdown signal was received and every lobby has wound down.
+///
+/// Lobbies get `SNAKES_SHUTDOWN_GRACE` seconds to finish up,
+/// during which clients are told the server is going away and no one new may join.
+pub async fn drain_on_signal() {
+    signal().await;
+    info!(
+        "shutting down, draining lobbies for {:?}",
+        CONFIG.shutdown_grace
+    );
+    DRAINING.store(true, Ordering::Relaxed);
+
+    // lobbies started by someone who got past `is_draining` just before it flipped show up
+    // later, they are told when they do with whatever is left of the grace period
+    let deadline = Instant::now() + CONFIG.shutdown_grace;
+    let mut told = HashSet::new();
+    let drained = tokio::time::timeout(CONFIG.shutdown_grace + Duration::from_secs(5), async {
+        loop {
+            let lobbies = {
+                let state = STATE.read().await;
+                if state.is_empty() {
+                    break;
+                }
+                state
+                    .iter()
+                    .filter(|(id, _)| !told.contains(*id))
+                    .map(|(&id, lobby)| (id, lobby.client_update.clone()))
+                    .collect::<Vec<_>>()
+            };
+            let grace = deadline.saturating_duration_since(Instant::now());
+            for (id, client_update) in lobbies {
+                told.insert(id);
+                _ = client_update.send(ClientUpdate::Shutdown(grace)).await;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await;
+    if drained.is_err() {
+        warn!("lobbies did not shut down in time");
+    }
+    info!("all lobbies closed");
+}
+
+async fn signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for ctrl-c");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => (),
+        _ = terminate => (),
+    }
+}
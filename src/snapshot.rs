@@ -0,0 +1,181 @@
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use axum::{
+    extract::Path as UrlPath,
+    http::{HeaderMap, StatusCode},
+};
+use serde::{Deserialize, Serialize};
+use snakes_shared::Direction;
+use tokio::sync::oneshot;
+
+use crate::{ClientUpdate, STATE, admin::is_admin, config::CONFIG, storage};
+
+/// Everything needed to bring a lobby back after a restart.
+///
+/// Connections can't survive a restart, so snakes are restored without an owner
+/// and are handed back to the first client that joins with the same name.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GameSnapshot {
+    pub id: usize,
+    pub map_size: (usize, usize),
+    pub tick: usize,
+    pub interval_ms: u64,
+    pub apples: Vec<(usize, usize)>,
+    pub id_counter: usize,
+    pub snakes: Vec<SnakeSnapshot>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SnakeSnapshot {
+    pub id: usize,
+    pub name: String,
+    pub position: (usize, usize),
+    pub direction: Direction,
+    pub tail: VecDeque<(usize, usize)>,
+    pub tail_len: usize,
+    pub death: usize,
+}
+
+fn path(dir: &Path, lobby: usize) -> PathBuf {
+    dir.join(format!("lobby-{lobby}.json"))
+}
+
+/// Writes the snapshot to `dir`, or removes the old one if there is nothing worth keeping.
+pub async fn save(dir: &Path, snapshot: &GameSnapshot) -> anyhow::Result<()> {
+    let path = path(dir, snapshot.id);
+    if snapshot.snakes.is_empty() {
+        return storage::remove_optional(&path).await;
+    }
+    let json = serde_json::to_vec(snapshot).context("failed encoding snapshot")?;
+    storage::write_atomic(&path, &json).await
+}
+
+pub async fn load(dir: &Path, lobby: usize) -> anyhow::Result<Option<GameSnapshot>> {
+    let path = path(dir, lobby);
+    let Some(json) = storage::read_optional(&path).await? else {
+        return Ok(None);
+    };
+    let snapshot = serde_json::from_slice(&json).context(format!("invalid {}", path.display()))?;
+    Ok(Some(snapshot))
+}
+
+/// The ids of every lobby saved in `dir`.
+pub async fn saved(dir: &Path) -> anyhow::Result<Vec<usize>> {
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context(format!("failed reading {}", dir.display())),
+    };
+    let mut lobbies = Vec::new();
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .context(format!("failed reading {}", dir.display()))?
+    {
+        let name = entry.file_name();
+        let lobby = name
+            .to_str()
+            .and_then(|name| name.strip_prefix("lobby-")?.strip_suffix(".json"))
+            .and_then(|id| id.parse::<usize>().ok());
+        lobbies.extend(lobby);
+    }
+    lobbies.sort();
+    Ok(lobbies)
+}
+
+/// `POST /lobbies/{id}/snapshot`, saves a running lobby right away. Needs
+/// `SNAKES_ADMIN_TOKEN` as a bearer token.
+pub async fn snapshot_lobby(
+    UrlPath(lobby): UrlPath<usize>,
+    headers: HeaderMap,
+) -> (StatusCode, String) {
+    if !is_admin(&headers) {
+        return (StatusCode::UNAUTHORIZED, "wrong admin token".to_string());
+    }
+    if CONFIG.snapshot_dir.is_none() {
+        return (StatusCode::CONFLICT, "snapshots are disabled".to_string());
+    }
+    let Some(info) = STATE.read().await.get(&lobby).cloned() else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("lobby {lobby} is not running"),
+        );
+    };
+    let (send, recv) = oneshot::channel();
+    if info
+        .client_update
+        .send(ClientUpdate::Snapshot(send))
+        .await
+        .is_err()
+    {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("lobby {lobby} is not running"),
+        );
+    }
+    match recv.await {
+        Ok(Ok(())) => (StatusCode::OK, format!("saved lobby {lobby}")),
+        Ok(Err(e)) => (StatusCode::INTERNAL_SERVER_ERROR, format!("{e:#}")),
+        Err(_) => (
+            StatusCode::NOT_FOUND,
+            format!("lobby {lobby} is not running"),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        extract::Path as UrlPath,
+        http::{HeaderMap, StatusCode},
+    };
+    use snakes_shared::Direction;
+
+    use super::{GameSnapshot, SnakeSnapshot, load, save, snapshot_lobby};
+
+    #[tokio::test]
+    async fn round_trip() {
+        let dir = std::env::temp_dir().join(format!("snakes-snapshot-{}", std::process::id()));
+        let mut snapshot = GameSnapshot {
+            id: 3,
+            map_size: (20, 14),
+            tick: 120,
+            interval_ms: 400,
+            apples: vec![(1, 2)],
+            id_counter: 2,
+            snakes: vec![SnakeSnapshot {
+                id: 1,
+                name: "Alfa".to_string(),
+                position: (4, 5),
+                direction: Direction::Up,
+                tail: [(4, 6), (4, 7)].into(),
+                tail_len: 2,
+                death: 7,
+            }],
+        };
+        assert!(load(&dir, 3).await.expect("load failed").is_none());
+        save(&dir, &snapshot).await.expect("save failed");
+        let loaded = load(&dir, 3)
+            .await
+            .expect("load failed")
+            .expect("missing snapshot");
+        assert_eq!(loaded.tick, 120);
+        assert_eq!(loaded.snakes[0].name, "Alfa");
+        assert_eq!(loaded.snakes[0].tail, snapshot.snakes[0].tail);
+
+        snapshot.snakes.clear();
+        save(&dir, &snapshot).await.expect("save failed");
+        assert!(load(&dir, 3).await.expect("load failed").is_none());
+        _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn only_admins_save_lobbies() {
+        let (status, _) = snapshot_lobby(UrlPath(0), HeaderMap::new()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,75 @@
+use axum::{
+    extract::{ConnectInfo, Query},
+    http::StatusCode,
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use futures_util::stream;
+use snakes_shared::WatchMessage;
+use std::net::SocketAddr;
+use tokio::sync::{broadcast::error::RecvError, mpsc};
+
+use crate::{
+    ClientUpdate, WSConnectInfo,
+    metrics::{METRICS, Metrics},
+    register_watcher, watchable,
+};
+
+/// Takes the watcher out of its lobby once the client goes away and the stream is dropped.
+struct Registration {
+    who: SocketAddr,
+    client_update: mpsc::Sender<ClientUpdate>,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let (who, client_update) = (self.who, self.client_update.clone());
+        tokio::spawn(async move {
+            _ = client_update.send(ClientUpdate::WatcherLeft(who)).await;
+            info!("{who}: closed event stream");
+        });
+    }
+}
+
+/// `GET /watch/sse?lobby=<id>`, the updates a watcher socket gets as server-sent events,
+/// for clients that can't keep a WebSocket open. Only board updates are sent, chat and
+/// inspecting need a socket.
+pub async fn watch_sse(
+    Query(WSConnectInfo { lobby, code, .. }): Query<WSConnectInfo>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Response {
+    let lobby = lobby.unwrap_or_default();
+    let client_update = match watchable(lobby, code.as_deref()).await {
+        Ok(client_update) => client_update,
+        Err(response) => return response,
+    };
+    let Some(pipe) = register_watcher(addr, lobby, &client_update).await else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("lobby {lobby} is not running"),
+        )
+            .into_response();
+    };
+    let registration = Registration {
+        who: addr,
+        client_update,
+    };
+    let updates = stream::unfold((pipe, registration), async |(mut pipe, registration)| {
+        loop {
+            match pipe.recv().await {
+                Ok(WatchMessage::Update(update)) => {
+                    let event = Event::default().json_data(update);
+                    return Some((event, (pipe, registration)));
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(n)) => Metrics::add(&METRICS.watcher_frames_dropped, n),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    Sse::new(updates)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
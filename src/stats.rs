@@ -0,0 +1,214 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{
+        LazyLock, Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use snakes_shared::{LeaderboardEntry, RatingEntry};
+
+use crate::{config::CONFIG, rating::INITIAL_RATING, storage};
+
+/// Per name statistics across all lobbies, kept in `SNAKES_STATS_FILE` when it is set.
+pub static STATS: LazyLock<Stats> =
+    LazyLock::new(|| Stats::load(CONFIG.stats_file.clone()).expect("failed loading player stats"));
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlayerStats {
+    /// Times the player joined a lobby and left it again, reconnects included.
+    pub sessions: u64,
+    /// Timed matches played to the end.
+    pub matches: u64,
+    pub max_length: usize,
+    pub apples: u64,
+    pub kills: u64,
+    pub deaths: u64,
+    /// Lives ended by either dying or leaving.
+    pub lives: u64,
+    pub survived_ticks: u64,
+    /// Only registered players get rated.
+    pub rating: Option<f64>,
+    pub rated_matches: u64,
+}
+
+impl PlayerStats {
+    /// Ends the current life, which started at tick `spawned_at`.
+    pub fn end_life(&mut self, spawned_at: usize, now: usize) {
+        self.lives += 1;
+        self.survived_ticks += now.saturating_sub(spawned_at) as u64;
+    }
+}
+
+pub struct Stats {
+    path: Option<PathBuf>,
+    players: Mutex<BTreeMap<String, PlayerStats>>,
+    dirty: AtomicBool,
+}
+
+impl Stats {
+    fn load(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let players = match &path {
+            Some(path) => match std::fs::read(path) {
+                Ok(json) => {
+                    serde_json::from_slice(&json).context(format!("invalid {}", path.display()))?
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+                Err(e) => Err(e).context(format!("failed reading {}", path.display()))?,
+            },
+            None => BTreeMap::new(),
+        };
+        Ok(Self {
+            path,
+            players: Mutex::new(players),
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    fn players(&self) -> MutexGuard<'_, BTreeMap<String, PlayerStats>> {
+        self.players.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn update(&self, name: &str, f: impl FnOnce(&mut PlayerStats)) {
+        let mut players = self.players();
+        match players.get_mut(name) {
+            Some(stats) => f(stats),
+            None => f(players.entry(name.to_string()).or_default()),
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    pub fn leaderboard(&self) -> Vec<LeaderboardEntry> {
+        let mut board = self
+            .players()
+            .iter()
+            .map(|(name, s)| LeaderboardEntry {
+                name: name.clone(),
+                sessions: s.sessions,
+                matches: s.matches,
+                max_length: s.max_length,
+                apples: s.apples,
+                kills: s.kills,
+                deaths: s.deaths,
+                avg_survival_ticks: match s.lives {
+                    0 => 0.0,
+                    lives => s.survived_ticks as f64 / lives as f64,
+                },
+            })
+            .collect::<Vec<_>>();
+        board.sort_by(|a, b| {
+            b.max_length
+                .cmp(&a.max_length)
+                .then(b.kills.cmp(&a.kills))
+                .then(a.name.cmp(&b.name))
+        });
+        board
+    }
+
+    pub fn rating(&self, name: &str) -> f64 {
+        self.players()
+            .get(name)
+            .and_then(|s| s.rating)
+            .unwrap_or(INITIAL_RATING)
+    }
+
+    pub fn ratings(&self) -> Vec<RatingEntry> {
+        let mut ratings = self
+            .players()
+            .iter()
+            .filter_map(|(name, s)| {
+                Some(RatingEntry {
+                    name: name.clone(),
+                    rating: s.rating?,
+                    matches: s.rated_matches,
+                })
+            })
+            .collect::<Vec<_>>();
+        ratings.sort_by(|a, b| b.rating.total_cmp(&a.rating));
+        ratings
+    }
+
+    /// Writes the stats to disk if anything changed since the last flush.
+    pub async fn flush(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let json = serde_json::to_vec(&*self.players()).context("failed encoding stats")?;
+        let written = storage::write_atomic(path, &json).await;
+        if written.is_err() {
+            // try again on the next flush
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        written
+    }
+}
+
+pub async fn flush_periodically() {
+    loop {
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        if let Err(e) = STATS.flush().await {
+            error!("failed to save player stats: {e:#}");
+        }
+    }
+}
+
+/// `GET /leaderboard`
+pub async fn serve_leaderboard() -> Json<Vec<LeaderboardEntry>> {
+    Json(STATS.leaderboard())
+}
+
+/// `GET /ratings`
+pub async fn serve_ratings() -> Json<Vec<RatingEntry>> {
+    Json(STATS.ratings())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Stats;
+
+    #[test]
+    fn leaderboard() {
+        let stats = Stats::load(None).expect("failed creating stats");
+        stats.update("Alfa", |s| {
+            s.max_length = 6;
+            s.end_life(0, 10);
+            s.end_life(10, 40);
+        });
+        stats.update("Bravo", |s| s.max_length = 9);
+        stats.update("Charlie", |s| {
+            s.max_length = 6;
+            s.kills = 1;
+        });
+        let board = stats.leaderboard();
+        let names = board.iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["Bravo", "Charlie", "Alfa"]);
+        assert_eq!(board[2].avg_survival_ticks, 20.0);
+        assert_eq!(board[0].avg_survival_ticks, 0.0);
+    }
+
+    #[tokio::test]
+    async fn failed_flush_is_retried() {
+        let dir = std::env::temp_dir().join(format!("snakes-stats-{}", std::process::id()));
+        let path = dir.join("stats.json");
+        let stats = Stats::load(Some(path.clone())).expect("failed creating stats");
+        stats.update("Alfa", |s| s.sessions += 1);
+        // the stats can't be written while a file is in the way of their directory
+        std::fs::write(&dir, "").expect("failed creating file");
+        assert!(stats.flush().await.is_err());
+
+        std::fs::remove_file(&dir).expect("failed removing file");
+        stats.flush().await.expect("flush failed");
+        let saved = Stats::load(Some(path)).expect("failed loading stats");
+        assert_eq!(saved.leaderboard()[0].sessions, 1);
+        _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,41 @@
+use std::path::Path;
+
+use anyhow::Context;
+
+/// Reads a file, treating a missing file as empty.
+pub async fn read_optional(path: &Path) -> anyhow::Result<Option<Vec<u8>>> {
+    match tokio::fs::read(path).await {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).context(format!("failed reading {}", path.display())),
+    }
+}
+
+/// Replaces a file by writing next to it and renaming, so readers never see half a file.
+pub async fn write_atomic(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent()
+        && !dir.as_os_str().is_empty()
+    {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .context(format!("failed creating {}", dir.display()))?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    tokio::fs::write(&tmp, data)
+        .await
+        .context(format!("failed writing {}", path.display()))?;
+    tokio::fs::rename(&tmp, path)
+        .await
+        .context(format!("failed writing {}", path.display()))?;
+    Ok(())
+}
+
+pub async fn remove_optional(path: &Path) -> anyhow::Result<()> {
+    match tokio::fs::remove_file(path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(e).context(format!("failed removing {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
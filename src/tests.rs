@@ -0,0 +1,606 @@
+use std::{net::SocketAddr, time::Duration};
+
+use async_tungstenite::{
+    WebSocketStream,
+    tokio::{ConnectStream, connect_async},
+    tungstenite::{Message, Utf8Bytes},
+};
+use futures_util::{Stream, StreamExt as _};
+use snakes_shared::{
+    Annotation, Appearance, ClientMessage, Direction, InspectEvent, ServerMessage, Skin,
+    TurnDirection, WatchMessage, WatchUpdate, WatcherMessage,
+};
+
+use axum::{
+    Json,
+    body::to_bytes,
+    extract::{ConnectInfo, Path as UrlPath, Query},
+    http::{
+        HeaderMap, HeaderValue, StatusCode, Uri,
+        header::{CONTENT_SECURITY_POLICY, CONTENT_TYPE, ETAG, IF_NONE_MATCH},
+    },
+};
+
+use crate::{
+    STATE, app, create_lobby,
+    frontend::{index, static_asset},
+    game::LobbySettings,
+    lobbies::{list_lobbies, lobby_state},
+    metrics::{LobbyGauges, METRICS},
+    replays::{list_replays, serve_replay},
+    restore_lobbies,
+    snapshot::{GameSnapshot, SnakeSnapshot, save},
+    sse::watch_sse,
+    stats::STATS,
+};
+
+async fn serve() -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("failed to bind address");
+    let addr = listener.local_addr().expect("no local address");
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            app().into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .expect("server crash");
+    });
+    addr
+}
+
+fn text(msg: &impl serde::Serialize) -> Message {
+    Message::Text(Utf8Bytes::from(
+        serde_json::to_string(msg).expect("failed encoding"),
+    ))
+}
+
+/// Waits until the lobby reports the given gauges, well before the next game tick.
+async fn wait_for_lobby(lobby: usize, players: usize, watchers: usize) {
+    let expected = LobbyGauges { players, watchers };
+    tokio::time::timeout(Duration::from_millis(500), async {
+        while METRICS.lobby(lobby) != Some(expected) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap_or_else(|_| panic!("lobby {lobby}: expected {expected:?}"));
+}
+
+/// Connects to `lobby` as a player called `name`.
+async fn join(addr: SocketAddr, lobby: usize, name: &str) -> WebSocketStream<ConnectStream> {
+    join_with_query(addr, lobby, "", name).await
+}
+
+/// Like [`join`], with `query` added to the URL.
+async fn join_with_query(
+    addr: SocketAddr,
+    lobby: usize,
+    query: &str,
+    name: &str,
+) -> WebSocketStream<ConnectStream> {
+    handshake(addr, &format!("lobby={lobby}{query}"), &[], name).await
+}
+
+/// Like [`join`], sending `first` before the name.
+async fn join_after(
+    addr: SocketAddr,
+    lobby: usize,
+    first: &[ClientMessage],
+    name: &str,
+) -> WebSocketStream<ConnectStream> {
+    handshake(addr, &format!("lobby={lobby}"), first, name).await
+}
+
+/// Connects to `/ws?{query}`, sending `first` and then the name.
+async fn handshake(
+    addr: SocketAddr,
+    query: &str,
+    first: &[ClientMessage],
+    name: &str,
+) -> WebSocketStream<ConnectStream> {
+    let (mut player, _) = connect_async(format!("ws://{addr}/ws?{query}"))
+        .await
+        .expect("failed to connect");
+    for msg in first {
+        player.send(text(msg)).await.expect("failed sending");
+    }
+    player
+        .send(text(&ClientMessage::SetName(name.to_string())))
+        .await
+        .expect("failed sending");
+    player
+}
+
+#[tokio::test]
+async fn watcher_disconnects_abruptly() {
+    let addr = serve().await;
+    let lobby = 2901;
+    let url = format!("ws://{addr}/watch?lobby={lobby}");
+
+    let (first, _) = connect_async(&url).await.expect("failed to connect");
+    wait_for_lobby(lobby, 0, 1).await;
+    drop(first);
+    wait_for_lobby(lobby, 0, 0).await;
+
+    let (mut second, _) = connect_async(&url).await.expect("failed to connect");
+    let Some(Ok(Message::Text(msg))) = second.next().await else {
+        panic!("lobby stopped sending updates");
+    };
+    serde_json::from_str::<WatchUpdate>(msg.as_str()).expect("invalid update");
+}
+
+#[tokio::test]
+async fn player_disconnects_abruptly() {
+    let addr = serve().await;
+    let lobby = 2902;
+
+    let player = join(addr, lobby, "abrupt").await;
+    let (watcher, _) = connect_async(format!("ws://{addr}/watch?lobby={lobby}&v=2"))
+        .await
+        .expect("failed to connect");
+    wait_for_lobby(lobby, 1, 1).await;
+    drop(player);
+    wait_for_lobby(lobby, 0, 1).await;
+    drop(watcher);
+    wait_for_lobby(lobby, 0, 0).await;
+}
+
+#[tokio::test]
+async fn lobby_full() {
+    let addr = serve().await;
+    let lobby = create_lobby(LobbySettings {
+        max_players: 1,
+        ..Default::default()
+    })
+    .await;
+    let _first = join(addr, lobby, "first").await;
+    wait_for_lobby(lobby, 1, 0).await;
+
+    let mut second = join(addr, lobby, "second").await;
+    let Some(Ok(Message::Text(msg))) = second.next().await else {
+        panic!("lobby did not answer");
+    };
+    let msg = serde_json::from_str::<ServerMessage>(msg.as_str()).expect("invalid message");
+    assert!(matches!(msg, ServerMessage::LobbyFull { max_players: 1 }));
+
+    let mut third = join_with_query(addr, lobby, "&watch_when_full=true", "third").await;
+    let Some(Ok(Message::Text(msg))) = third.next().await else {
+        panic!("lobby did not answer");
+    };
+    let msg = serde_json::from_str::<ServerMessage>(msg.as_str()).expect("invalid message");
+    assert!(matches!(msg, ServerMessage::LobbyFull { max_players: 1 }));
+    wait_for_lobby(lobby, 1, 1).await;
+    let Some(Ok(Message::Text(msg))) = third.next().await else {
+        panic!("lobby stopped sending updates");
+    };
+    serde_json::from_str::<WatchUpdate>(msg.as_str()).expect("invalid update");
+}
+
+#[tokio::test]
+async fn malicious_name() {
+    let addr = serve().await;
+    let lobby = 2903;
+
+    let (mut watcher, _) = connect_async(format!("ws://{addr}/watch?lobby={lobby}&v=2"))
+        .await
+        .expect("failed to connect");
+    let _player = join(addr, lobby, "<img src=x onerror=alert(1)>").await;
+    wait_for_lobby(lobby, 1, 1).await;
+
+    let name = loop {
+        let Some(Ok(Message::Text(msg))) = watcher.next().await else {
+            panic!("lobby stopped sending updates");
+        };
+        let Ok(WatchMessage::Update(update)) = serde_json::from_str(msg.as_str()) else {
+            panic!("invalid update");
+        };
+        if let Some(client) = update.clients.into_iter().next() {
+            break client.name;
+        }
+    };
+    assert_eq!(name, "img src=x onerror=alert(1)");
+}
+
+#[tokio::test]
+async fn page_runs_only_static_scripts() {
+    let page = index(HeaderMap::new()).await;
+    let csp = page
+        .headers()
+        .get(CONTENT_SECURITY_POLICY)
+        .and_then(|csp| csp.to_str().ok())
+        .expect("no content security policy")
+        .to_string();
+    assert!(csp.contains("script-src 'self';"));
+    assert!(!csp.contains("unsafe-inline"));
+    let html = to_bytes(page.into_body(), usize::MAX)
+        .await
+        .expect("failed reading page");
+    let html = String::from_utf8_lossy(&html);
+    assert!(html.contains("<script src=\"/static/index.js\"></script>"));
+    assert!(!html.contains("<script>") && !html.contains("style=\""));
+}
+
+#[tokio::test]
+async fn static_assets_revalidate() {
+    let asset = static_asset(UrlPath("palette.js".to_string()), HeaderMap::new()).await;
+    assert_eq!(asset.status(), StatusCode::OK);
+    assert_eq!(
+        asset
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok()),
+        Some("text/javascript; charset=utf-8")
+    );
+    let etag = asset.headers().get(ETAG).expect("no etag").clone();
+
+    let mut request = HeaderMap::new();
+    request.insert(IF_NONE_MATCH, etag.clone());
+    let cached = static_asset(UrlPath("palette.js".to_string()), request).await;
+    assert_eq!(cached.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(cached.headers().get(ETAG), Some(&etag));
+
+    let mut request = HeaderMap::new();
+    request.insert(IF_NONE_MATCH, HeaderValue::from_static("\"stale\""));
+    let stale = static_asset(UrlPath("palette.js".to_string()), request).await;
+    assert_eq!(stale.status(), StatusCode::OK);
+
+    let missing = static_asset(UrlPath("..%2Fmain.rs".to_string()), HeaderMap::new()).await;
+    assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn chosen_appearance() {
+    let addr = serve().await;
+    let lobby = 2905;
+
+    let (mut watcher, _) = connect_async(format!("ws://{addr}/watch?lobby={lobby}&v=2"))
+        .await
+        .expect("failed to connect");
+    let appearance = ClientMessage::Appearance(Appearance {
+        color: Some("#FF8800".to_string()),
+        skin: Skin::Dotted,
+    });
+    let _player = join_after(addr, lobby, &[appearance], "Dressed").await;
+
+    let appearance = loop {
+        let Some(Ok(Message::Text(msg))) = watcher.next().await else {
+            panic!("lobby stopped sending updates");
+        };
+        let Ok(WatchMessage::Update(update)) = serde_json::from_str(msg.as_str()) else {
+            continue;
+        };
+        if let Some(client) = update.clients.into_iter().next() {
+            break client.appearance;
+        }
+    };
+    assert_eq!(appearance.color.as_deref(), Some("#ff8800"));
+    assert_eq!(appearance.skin, Skin::Dotted);
+}
+
+#[tokio::test]
+async fn match_replay() {
+    let addr = serve().await;
+    let lobby = create_lobby(LobbySettings {
+        match_ticks: Some(3),
+        single_match: true,
+        tick_ms: Some(20),
+        ..Default::default()
+    })
+    .await;
+    let _player = join(addr, lobby, "Replayed").await;
+
+    let replay = tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            let Json(replays) = list_replays().await;
+            if let Some(replay) = replays.into_iter().find(|r| r.lobby == lobby) {
+                break replay;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the match was not recorded");
+    assert_eq!(replay.ticks, 3);
+    assert_eq!(replay.standings[0].name, "Replayed");
+    let played = STATS
+        .leaderboard()
+        .into_iter()
+        .find(|entry| entry.name == "Replayed")
+        .map(|entry| entry.matches);
+    assert_eq!(played, Some(1));
+
+    let response = serve_replay(UrlPath(replay.id)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn inspect_snake() {
+    let addr = serve().await;
+    let lobby = create_lobby(LobbySettings {
+        tick_ms: Some(50),
+        ..Default::default()
+    })
+    .await;
+
+    let (mut watcher, _) = connect_async(format!("ws://{addr}/watch?lobby={lobby}&v=2"))
+        .await
+        .expect("failed to connect");
+    let mut player = join(addr, lobby, "Inspected").await;
+    wait_for_lobby(lobby, 1, 1).await;
+    watcher
+        .send(text(&WatcherMessage::Inspect(Some(0))))
+        .await
+        .expect("failed sending");
+
+    let tick_id = loop {
+        let Some(Ok(Message::Text(msg))) = player.next().await else {
+            panic!("lobby stopped sending ticks");
+        };
+        if let Ok(ServerMessage::Tick { tick_id, .. }) = serde_json::from_str(msg.as_str()) {
+            break tick_id;
+        }
+    };
+    player
+        .send(text(&ClientMessage::NoTurn(tick_id)))
+        .await
+        .expect("failed sending");
+    player
+        .send(text(&ClientMessage::Turn(
+            tick_id,
+            TurnDirection::Clockwise,
+        )))
+        .await
+        .expect("failed sending");
+
+    let mut events = Vec::new();
+    tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            let Some(Ok(Message::Text(msg))) = watcher.next().await else {
+                panic!("lobby stopped sending updates");
+            };
+            if let Ok(WatchMessage::Inspect(event)) = serde_json::from_str(msg.as_str()) {
+                let applied = matches!(
+                    event,
+                    InspectEvent::Applied {
+                        answered: Some(_),
+                        ..
+                    }
+                );
+                events.push(event);
+                if applied {
+                    break;
+                }
+            }
+        }
+    })
+    .await
+    .expect("no answer was applied");
+    assert!(events.iter().any(|e| matches!(
+        e,
+        InspectEvent::Answered {
+            snake: 0,
+            tick_id: id,
+            turn: Some(TurnDirection::Clockwise),
+            after_ms: Some(_),
+        } if *id == tick_id
+    )));
+    assert_eq!(
+        events.last(),
+        Some(&InspectEvent::Applied {
+            snake: 0,
+            answered: Some(tick_id),
+            turn: Some(TurnDirection::Clockwise),
+            dropped: 1,
+        })
+    );
+}
+
+#[tokio::test]
+async fn debug_annotations() {
+    let addr = serve().await;
+    let lobby = create_lobby(LobbySettings {
+        tick_ms: Some(50),
+        ..Default::default()
+    })
+    .await;
+
+    let (mut watcher, _) = connect_async(format!("ws://{addr}/watch?lobby={lobby}&v=2"))
+        .await
+        .expect("failed to connect");
+    let mut player = join(addr, lobby, "Planner").await;
+    let tick_id = loop {
+        let Some(Ok(Message::Text(msg))) = player.next().await else {
+            panic!("lobby stopped sending ticks");
+        };
+        if let Ok(ServerMessage::Tick { tick_id, .. }) = serde_json::from_str(msg.as_str()) {
+            break tick_id;
+        }
+    };
+    let label = Annotation::Label {
+        at: (1, 1),
+        text: "x".repeat(100),
+    };
+    for annotations in [vec![Annotation::Target((1, 2)), label], Vec::new()] {
+        player
+            .send(text(&ClientMessage::Debug {
+                tick_id,
+                annotations,
+            }))
+            .await
+            .expect("failed sending");
+    }
+
+    let annotations = tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            let Some(Ok(Message::Text(msg))) = watcher.next().await else {
+                panic!("lobby stopped sending updates");
+            };
+            if let Ok(WatchMessage::Debug {
+                snake: 0,
+                tick_id: id,
+                annotations,
+            }) = serde_json::from_str(msg.as_str())
+            {
+                assert_eq!(id, tick_id);
+                break annotations;
+            }
+        }
+    })
+    .await
+    .expect("annotations were not forwarded");
+    // only the first message of the tick is passed on, with the label cut short
+    assert_eq!(
+        annotations,
+        vec![
+            Annotation::Target((1, 2)),
+            Annotation::Label {
+                at: (1, 1),
+                text: "x".repeat(64),
+            },
+        ]
+    );
+}
+
+/// Reads until `n` chat messages came in, skipping everything else.
+async fn chat_messages<S, E>(watcher: &mut S, n: usize) -> Vec<WatchMessage>
+where
+    S: Stream<Item = Result<Message, E>> + Unpin,
+{
+    let mut got = Vec::new();
+    while got.len() < n {
+        let Some(Ok(Message::Text(msg))) = watcher.next().await else {
+            panic!("lobby stopped sending updates");
+        };
+        if let Ok(msg @ (WatchMessage::Chat { .. } | WatchMessage::ChatRefused { .. })) =
+            serde_json::from_str(msg.as_str())
+        {
+            got.push(msg);
+        }
+    }
+    got
+}
+
+#[tokio::test]
+async fn spectator_chat() {
+    let addr = serve().await;
+    let lobby = 2904;
+    let url = format!("ws://{addr}/watch?lobby={lobby}&v=2");
+    let (mut fan, _) = connect_async(&url).await.expect("failed to connect");
+    let (mut other, _) = connect_async(&url).await.expect("failed to connect");
+    wait_for_lobby(lobby, 0, 2).await;
+
+    fan.send(text(&WatcherMessage::SetName("<Fan>".to_string())))
+        .await
+        .expect("failed sending");
+    for i in 0..6 {
+        fan.send(text(&WatcherMessage::Chat(format!("go\u{7} {i}"))))
+            .await
+            .expect("failed sending");
+    }
+
+    let got = chat_messages(&mut other, 5).await;
+    assert_eq!(
+        got[4],
+        WatchMessage::Chat {
+            name: "Fan".to_string(),
+            text: "go 4".to_string(),
+        }
+    );
+    let got = chat_messages(&mut fan, 6).await;
+    assert!(matches!(got[5], WatchMessage::ChatRefused { .. }));
+}
+
+#[tokio::test]
+async fn watch_without_websockets() {
+    let addr = serve().await;
+    let lobby = 2906;
+    let _player = join(addr, lobby, "Polled").await;
+    wait_for_lobby(lobby, 1, 0).await;
+    let query = |uri: &'static str| Query::try_from_uri(&Uri::from_static(uri)).expect("bad query");
+
+    let state = lobby_state(UrlPath(lobby), query("/lobbies/2906/state")).await;
+    assert_eq!(state.status(), StatusCode::OK);
+    let state = to_bytes(state.into_body(), usize::MAX)
+        .await
+        .expect("failed reading state");
+    let state = serde_json::from_slice::<WatchUpdate>(&state).expect("invalid state");
+    assert_eq!(state.clients.len(), 1);
+    assert_eq!(state.clients[0].name, "Polled");
+    wait_for_lobby(lobby, 1, 0).await;
+    // polling doesn't start lobbies nobody plays in
+    let idle = lobby_state(UrlPath(2909), query("/lobbies/2909/state")).await;
+    assert_eq!(idle.status(), StatusCode::NOT_FOUND);
+    assert!(!STATE.read().await.contains_key(&2909));
+
+    let events = watch_sse(
+        query("/watch/sse?lobby=2906"),
+        ConnectInfo("127.0.0.1:2".parse().expect("bad address")),
+    )
+    .await;
+    assert_eq!(
+        events
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok()),
+        Some("text/event-stream")
+    );
+    let mut events = events.into_body().into_data_stream();
+    let event = tokio::time::timeout(Duration::from_secs(2), events.next())
+        .await
+        .expect("no event in time")
+        .and_then(|event| event.ok())
+        .expect("event stream ended");
+    let event = String::from_utf8_lossy(&event);
+    let data = event
+        .strip_prefix("data: ")
+        .expect("not a data event")
+        .trim_end();
+    let update = serde_json::from_str::<WatchUpdate>(data).expect("invalid update");
+    assert_eq!(update.clients[0].name, "Polled");
+    wait_for_lobby(lobby, 1, 1).await;
+    drop(events);
+    wait_for_lobby(lobby, 1, 0).await;
+
+    let private = watch_sse(
+        query("/watch/sse?lobby=1000000"),
+        ConnectInfo("127.0.0.1:3".parse().expect("bad address")),
+    )
+    .await;
+    assert_eq!(private.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn restore_on_boot() {
+    let dir = std::env::temp_dir().join(format!("snakes-boot-{}", std::process::id()));
+    let lobby = 2907;
+    let snapshot = GameSnapshot {
+        id: lobby,
+        map_size: (20, 14),
+        tick: 120,
+        interval_ms: 400,
+        apples: vec![(1, 2)],
+        id_counter: 2,
+        snakes: vec![SnakeSnapshot {
+            id: 1,
+            name: "Sleeper".to_string(),
+            position: (4, 5),
+            direction: Direction::Up,
+            tail: [(4, 6), (4, 7)].into(),
+            tail_len: 2,
+            death: 0,
+        }],
+    };
+    save(&dir, &snapshot).await.expect("save failed");
+
+    restore_lobbies(&dir).await;
+    let Json(lobbies) = list_lobbies().await;
+    let restored = lobbies
+        .iter()
+        .find(|l| l.id == lobby)
+        .expect("lobby was not restored");
+    assert_eq!(restored.tick, 120);
+    assert_eq!(restored.players, 0);
+    _ = std::fs::remove_dir_all(&dir);
+}
@@ -19,6 +19,7 @@ impl<const N: usize> TickBuffer<N> {
         self.inner[self.index] = rand::random();
         val
     }
+    #[allow(dead_code)]
     pub fn current(&self) -> u64 {
         self.inner[self.index]
     }
@@ -36,6 +37,7 @@ impl<const N: usize> TickBuffer<N> {
     }
 }
 
+#[cfg(test)]
 mod tests {
     #[test]
     fn overflow() {
@@ -0,0 +1,398 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet},
+    sync::{LazyLock, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use axum::{
+    Json,
+    extract::Path as UrlPath,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures_util::{StreamExt as _, stream::FuturesUnordered};
+use serde::Deserialize;
+use snakes_shared::{
+    Standing, TournamentInfo, TournamentMatch, TournamentStanding, TournamentStatus,
+};
+use tokio::{
+    sync::{mpsc, watch},
+    time::{Instant, timeout_at},
+};
+
+use crate::{
+    accounts::Accounts, admin::is_admin, config::CONFIG, create_lobby, game::LobbySettings,
+};
+
+/// How long a bot connecting between two of its matches waits to be placed in the next one.
+const ASSIGNMENT_WAIT: Duration = Duration::from_secs(60);
+
+static TOURNAMENTS: LazyLock<Mutex<BTreeMap<usize, Tournament>>> = LazyLock::new(Default::default);
+
+fn tournaments() -> MutexGuard<'static, BTreeMap<usize, Tournament>> {
+    TOURNAMENTS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    /// Keep playing rounds until every bot has met every other bot.
+    #[default]
+    RoundRobin,
+    /// A fixed number of rounds, each grouping bots with about as many points.
+    Swiss { rounds: usize },
+}
+
+/// Body of `POST /tournaments`.
+#[derive(Debug, Deserialize)]
+pub struct NewTournament {
+    bots: Vec<String>,
+    #[serde(default)]
+    format: Format,
+    #[serde(default = "default_players_per_match")]
+    players_per_match: usize,
+    #[serde(default = "default_match_ticks")]
+    match_ticks: usize,
+}
+fn default_players_per_match() -> usize {
+    4
+}
+fn default_match_ticks() -> usize {
+    500
+}
+
+struct Tournament {
+    info: TournamentInfo,
+    format: Format,
+    players_per_match: usize,
+    match_ticks: usize,
+    /// Round robin pairings are drawn up front.
+    schedule: Vec<Vec<Vec<String>>>,
+    /// Bumped whenever a round starts or a match finishes.
+    changed: watch::Sender<()>,
+}
+
+impl Tournament {
+    fn next_round(&self, round: usize) -> Option<Vec<Vec<String>>> {
+        match self.format {
+            Format::RoundRobin => self.schedule.get(round).cloned(),
+            Format::Swiss { rounds } if round < rounds => {
+                Some(swiss_round(&self.info.standings, self.players_per_match))
+            }
+            Format::Swiss { .. } => None,
+        }
+    }
+
+    fn record(&mut self, round: usize, index: usize, standings: Option<Vec<Standing>>) {
+        let Some(game) = self
+            .info
+            .rounds
+            .get_mut(round)
+            .and_then(|r| r.get_mut(index))
+        else {
+            return;
+        };
+        game.finished = true;
+        if let Some(standings) = &standings {
+            for (name, points) in points(standings) {
+                if let Some(s) = self.info.standings.iter_mut().find(|s| s.name == name) {
+                    s.points += points;
+                    s.matches += 1;
+                }
+            }
+            self.info.standings.sort_by(|a, b| {
+                b.points
+                    .total_cmp(&a.points)
+                    .then_with(|| a.name.cmp(&b.name))
+            });
+        }
+        game.standings = standings;
+        self.changed.send_replace(());
+    }
+
+    /// The lobby of the unfinished match `name` has in the current round.
+    fn lobby_for(&self, name: &str) -> Option<usize> {
+        self.info
+            .rounds
+            .last()?
+            .iter()
+            .find(|m| !m.finished && m.players.iter().any(|p| p == name))
+            .map(|m| m.lobby)
+    }
+}
+
+/// Groups of up to `size` bots, round after round, until every bot has met every
+/// other bot at least once. Groups that would only bring together bots that already
+/// met are left out, so some bots sit a round out.
+fn round_robin(bots: &[String], size: usize) -> Vec<Vec<Vec<String>>> {
+    fn new_pairs(unmet: &BTreeSet<(usize, usize)>, bot: usize, group: &[usize]) -> usize {
+        group
+            .iter()
+            .filter(|&&other| unmet.contains(&(bot.min(other), bot.max(other))))
+            .count()
+    }
+
+    let mut unmet = BTreeSet::new();
+    for a in 0..bots.len() {
+        for b in a + 1..bots.len() {
+            unmet.insert((a, b));
+        }
+    }
+    let mut rounds = Vec::new();
+    while !unmet.is_empty() {
+        let mut free = (0..bots.len()).collect::<Vec<_>>();
+        let mut round = Vec::new();
+        while free.len() > 1 {
+            let Some(seed) = free
+                .iter()
+                .copied()
+                .max_by_key(|&bot| (new_pairs(&unmet, bot, &free), Reverse(bot)))
+            else {
+                break;
+            };
+            free.retain(|&bot| bot != seed);
+            let mut group = vec![seed];
+            while group.len() < size {
+                let Some((index, _)) = free
+                    .iter()
+                    .enumerate()
+                    .max_by_key(|&(_, &bot)| (new_pairs(&unmet, bot, &group), Reverse(bot)))
+                else {
+                    break;
+                };
+                group.push(free.remove(index));
+            }
+            if group.iter().any(|&bot| new_pairs(&unmet, bot, &group) > 0) {
+                round.push(group);
+            }
+        }
+        for group in &round {
+            for &a in group {
+                for &b in group {
+                    unmet.remove(&(a.min(b), a.max(b)));
+                }
+            }
+        }
+        rounds.push(
+            round
+                .into_iter()
+                .map(|group| group.into_iter().map(|bot| bots[bot].clone()).collect())
+                .collect(),
+        );
+    }
+    rounds
+}
+
+/// Chunks the standings into groups of `size`, a bot left on its own sits the round out.
+fn swiss_round(standings: &[TournamentStanding], size: usize) -> Vec<Vec<String>> {
+    standings
+        .chunks(size)
+        .filter(|group| group.len() > 1)
+        .map(|group| group.iter().map(|s| s.name.clone()).collect())
+        .collect()
+}
+
+fn points(standings: &[Standing]) -> impl Iterator<Item = (&str, f64)> {
+    standings.iter().map(|s| {
+        let beaten = standings.iter().filter(|o| o.place > s.place).count();
+        let tied = standings.iter().filter(|o| o.place == s.place).count() - 1;
+        (s.name.as_str(), beaten as f64 + tied as f64 / 2.0)
+    })
+}
+
+/// Plays the rounds one after another, every match in its own lobby.
+async fn run(id: usize) {
+    for round in 0.. {
+        let Some((groups, match_ticks)) = tournaments()
+            .get(&id)
+            .and_then(|t| Some((t.next_round(round)?, t.match_ticks)))
+        else {
+            break;
+        };
+        let mut matches = Vec::new();
+        let mut results = FuturesUnordered::new();
+        for (index, players) in groups.into_iter().enumerate() {
+            let (send, mut recv) = mpsc::channel(1);
+            let lobby = create_lobby(LobbySettings {
+                match_ticks: Some(match_ticks),
+                max_players: players.len(),
+                roster: Some(players.clone()),
+                single_match: true,
+                results: Some(send),
+                ..Default::default()
+            })
+            .await;
+            info!(
+                "tournament {id}: round {round}: {} in lobby {lobby}",
+                players.join(", ")
+            );
+            matches.push(TournamentMatch {
+                lobby,
+                players,
+                finished: false,
+                standings: None,
+            });
+            results.push(async move { (index, recv.recv().await) });
+        }
+        if let Some(t) = tournaments().get_mut(&id) {
+            t.info.status = TournamentStatus::Running { round };
+            t.info.rounds.push(matches);
+            t.changed.send_replace(());
+        }
+        while let Some((index, standings)) = results.next().await {
+            if standings.is_none() {
+                warn!("tournament {id}: round {round}: match {index} was abandoned");
+            }
+            if let Some(t) = tournaments().get_mut(&id) {
+                t.record(round, index, standings);
+            }
+        }
+    }
+    if let Some(t) = tournaments().get_mut(&id) {
+        info!("tournament {id}: finished");
+        t.info.status = TournamentStatus::Finished;
+        t.changed.send_replace(());
+    }
+}
+
+/// `POST /tournaments`, needs `SNAKES_ADMIN_TOKEN` as a bearer token since it drafts
+/// registered bots into matches.
+pub async fn create_tournament(headers: HeaderMap, Json(new): Json<NewTournament>) -> Response {
+    if !is_admin(&headers) {
+        return (StatusCode::UNAUTHORIZED, "wrong admin token").into_response();
+    }
+    let mut bots = Vec::<String>::new();
+    for bot in new.bots {
+        if !bots.contains(&bot) {
+            bots.push(bot);
+        }
+    }
+    if bots.len() < 2 || new.players_per_match < 2 {
+        return (
+            StatusCode::BAD_REQUEST,
+            "a tournament needs at least two bots and two players per match",
+        )
+            .into_response();
+    }
+    let accounts = match Accounts::load(&CONFIG.token_file).await {
+        Ok(accounts) => accounts,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("{e:#}")).into_response(),
+    };
+    if let Some(bot) = bots.iter().find(|b| !accounts.is_registered(b)) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("{bot} is not a registered bot"),
+        )
+            .into_response();
+    }
+
+    let mut all = tournaments();
+    let id = all.keys().last().map_or(1, |id| id + 1);
+    let info = TournamentInfo {
+        id,
+        status: TournamentStatus::Running { round: 0 },
+        rounds: Vec::new(),
+        standings: bots
+            .iter()
+            .map(|name| TournamentStanding {
+                name: name.clone(),
+                points: 0.0,
+                matches: 0,
+            })
+            .collect(),
+    };
+    let schedule = match new.format {
+        Format::RoundRobin => round_robin(&bots, new.players_per_match),
+        Format::Swiss { .. } => Vec::new(),
+    };
+    all.insert(
+        id,
+        Tournament {
+            info: info.clone(),
+            format: new.format,
+            players_per_match: new.players_per_match,
+            match_ticks: new.match_ticks,
+            schedule,
+            changed: watch::Sender::new(()),
+        },
+    );
+    drop(all);
+    info!("tournament {id}: started with {}", bots.join(", "));
+    tokio::spawn(run(id));
+    (StatusCode::CREATED, Json(info)).into_response()
+}
+
+/// `GET /tournaments/{id}`
+pub async fn serve_tournament(UrlPath(id): UrlPath<usize>) -> Response {
+    match tournaments().get(&id) {
+        Some(t) => Json(t.info.clone()).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("no tournament {id}")).into_response(),
+    }
+}
+
+/// Finds the lobby `name` should play its next match of tournament `id` in,
+/// waiting a while for the next round to start if needed.
+pub async fn assignment(id: usize, name: &str) -> Result<usize, String> {
+    let deadline = Instant::now() + ASSIGNMENT_WAIT;
+    loop {
+        let mut changed = {
+            let all = tournaments();
+            let t = all.get(&id).ok_or(format!("no tournament {id}"))?;
+            if !t.info.standings.iter().any(|s| s.name == name) {
+                return Err(format!("{name} is not playing in tournament {id}"));
+            }
+            if let Some(lobby) = t.lobby_for(name) {
+                return Ok(lobby);
+            }
+            if t.info.status == TournamentStatus::Finished {
+                return Err(format!("tournament {id} is over"));
+            }
+            t.changed.subscribe()
+        };
+        if timeout_at(deadline, changed.changed()).await.is_err() {
+            return Err(format!("no match in tournament {id} for {name} right now"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use axum::{
+        Json,
+        http::{HeaderMap, StatusCode},
+    };
+
+    use super::{create_tournament, round_robin};
+
+    #[test]
+    fn everyone_meets() {
+        let bots = (0..7).map(|i| format!("bot{i}")).collect::<Vec<_>>();
+        for size in [2, 3, 4] {
+            let rounds = round_robin(&bots, size);
+            let mut met = BTreeSet::new();
+            for round in &rounds {
+                let mut seen = BTreeSet::new();
+                for group in round {
+                    assert!(group.len() > 1 && group.len() <= size);
+                    for a in group {
+                        assert!(seen.insert(a), "{a} plays twice in a round");
+                        for b in group {
+                            met.insert((a, b));
+                        }
+                    }
+                }
+            }
+            assert_eq!(met.len(), bots.len() * bots.len());
+        }
+    }
+
+    #[tokio::test]
+    async fn only_admins_start_tournaments() {
+        let new = serde_json::from_str(r#"{"bots": ["Alfa", "Bravo"]}"#).expect("invalid json");
+        let response = create_tournament(HeaderMap::new(), Json(new)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}